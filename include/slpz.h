@@ -0,0 +1,159 @@
+/* Generated by cbindgen from src/ffi.rs. Do not edit. */
+
+#ifndef SLPZ_H
+#define SLPZ_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define SLPZ_OK 0
+
+/**
+ * Mirrors `CompError::InvalidFile` and `DecompError::InvalidFile`.
+ */
+#define SLPZ_ERR_INVALID_FILE 1
+
+/**
+ * Mirrors `CompError::CompressionFailure`.
+ */
+#define SLPZ_ERR_COMPRESSION_FAILURE 2
+
+/**
+ * Mirrors `DecompError::DecompressionFailure`.
+ */
+#define SLPZ_ERR_DECOMPRESSION_FAILURE 3
+
+/**
+ * The output buffer is too small. The required size is written to `out_len`.
+ */
+#define SLPZ_ERR_BUFFER_TOO_SMALL 4
+
+/**
+ * A required pointer was null.
+ */
+#define SLPZ_ERR_NULL_POINTER 5
+
+/**
+ * Opaque compression context.
+ */
+typedef struct SlpzCompressor SlpzCompressor;
+
+/**
+ * Opaque decompression context.
+ */
+typedef struct SlpzDecompressor SlpzDecompressor;
+
+/**
+ * The header at the start of every slpz file. Offsets are from file start.
+ */
+typedef struct SlpzHeader {
+  uint32_t version;
+  size_t event_sizes_offset;
+  size_t game_start_offset;
+  size_t metadata_offset;
+  size_t compressed_events_offset;
+  size_t decompressed_events_size;
+  /**
+   * The exact size of the slp file this slpz file decompresses to.
+   */
+  size_t decompressed_size;
+} SlpzHeader;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates a compressor. compression_level should be between 1..=19. The default is 3.
+ *
+ * Returns null on failure. Must be freed with `slpz_compressor_free`.
+ */
+struct SlpzCompressor *slpz_compressor_new(int32_t compression_level);
+
+/**
+ * Frees a compressor. Passing null does nothing.
+ *
+ * # Safety
+ * `compressor` must be null or a pointer returned by `slpz_compressor_new` that has not been freed.
+ */
+void slpz_compressor_free(struct SlpzCompressor *compressor);
+
+/**
+ * Creates a decompressor.
+ *
+ * Returns null on failure. Must be freed with `slpz_decompressor_free`.
+ */
+struct SlpzDecompressor *slpz_decompressor_new(void);
+
+/**
+ * Frees a decompressor. Passing null does nothing.
+ *
+ * # Safety
+ * `decompressor` must be null or a pointer returned by `slpz_decompressor_new` that has not been freed.
+ */
+void slpz_decompressor_free(struct SlpzDecompressor *decompressor);
+
+/**
+ * Returns an upper bound on the size of the slpz file an slp file of `slp_len` bytes compresses to.
+ */
+size_t slpz_compress_bound(size_t slp_len);
+
+/**
+ * Compresses an slp file into `out`.
+ *
+ * The size of the slpz file is written to `out_len`.
+ * If `out_cap` is too small, nothing is written to `out`, `SLPZ_ERR_BUFFER_TOO_SMALL` is returned,
+ * and the required size is written to `out_len`.
+ * Use `slpz_compress_bound` to size `out` beforehand.
+ *
+ * # Safety
+ * `slp` must point to `slp_len` readable bytes and `out` must point to `out_cap` writable bytes.
+ * `compressor` must be a valid compressor.
+ */
+int32_t slpz_compress(struct SlpzCompressor *compressor,
+                      const uint8_t *slp,
+                      size_t slp_len,
+                      uint8_t *out,
+                      size_t out_cap,
+                      size_t *out_len);
+
+/**
+ * Decompresses an slpz file into `out`.
+ *
+ * The size of the slp file is written to `out_len`.
+ * If `out_cap` is too small, nothing is written to `out`, `SLPZ_ERR_BUFFER_TOO_SMALL` is returned,
+ * and the required size is written to `out_len`.
+ * Use `slpz_decompressed_size` to size `out` beforehand.
+ *
+ * # Safety
+ * `slpz` must point to `slpz_len` readable bytes and `out` must point to `out_cap` writable bytes.
+ * `decompressor` must be a valid decompressor.
+ */
+int32_t slpz_decompress(struct SlpzDecompressor *decompressor,
+                        const uint8_t *slpz,
+                        size_t slpz_len,
+                        uint8_t *out,
+                        size_t out_cap,
+                        size_t *out_len);
+
+/**
+ * Writes the exact size of the slp file an slpz file decompresses to into `out_size`.
+ *
+ * # Safety
+ * `slpz` must point to `slpz_len` readable bytes.
+ */
+int32_t slpz_decompressed_size(const uint8_t *slpz, size_t slpz_len, size_t *out_size);
+
+/**
+ * Reads and validates the header of an slpz file into `out_header`.
+ *
+ * # Safety
+ * `slpz` must point to `slpz_len` readable bytes.
+ */
+int32_t slpz_read_header(const uint8_t *slpz, size_t slpz_len, struct SlpzHeader *out_header);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* SLPZ_H */
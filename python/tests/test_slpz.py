@@ -0,0 +1,70 @@
+# Run with `maturin develop && pytest python/tests`.
+
+import struct
+
+import pytest
+
+import slpz
+
+GAME_START = 0x36
+PRE_FRAME = 0x37
+FRAME_START = 0x3A
+SIZES = {GAME_START: 0x2FC, PRE_FRAME: 0x3F, FRAME_START: 0x0C}
+RAW_HEADER = bytes([0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C])
+METADATA = b"U\x08metadata{U\x07startAtSU\x142024-01-01T12:00:00Z}}"
+
+
+def make_slp(frames=100):
+    payloads = bytes([0x35, 1 + 3 * len(SIZES)])
+    for command, size in SIZES.items():
+        payloads += bytes([command]) + struct.pack(">H", size)
+
+    events = bytes([GAME_START]) + bytes(SIZES[GAME_START])
+    for frame in range(-123, -123 + frames):
+        for command in (FRAME_START, PRE_FRAME, PRE_FRAME):
+            payload = bytearray(SIZES[command])
+            struct.pack_into(">i", payload, 0, frame)
+            events += bytes([command]) + payload
+
+    raw = payloads + events
+    return RAW_HEADER + struct.pack(">I", len(raw)) + raw + METADATA
+
+
+def test_round_trip():
+    slp = make_slp()
+    compressed = slpz.compress(slp)
+    assert len(compressed) < len(slp)
+    assert slpz.decompress(compressed) == slp
+
+
+def test_contexts_are_reusable():
+    compressor = slpz.Compressor(level=12)
+    decompressor = slpz.Decompressor()
+    for frames in (1, 50, 500):
+        slp = make_slp(frames)
+        assert decompressor.decompress(compressor.compress(slp)) == slp
+
+
+def test_header():
+    slp = make_slp()
+    compressed = slpz.compress(slp)
+    header = slpz.read_header(compressed)
+    assert header.version == slpz.VERSION
+    assert header.event_sizes_offset == 24
+    assert header.decompressed_size == len(slp)
+    assert slpz.decompressed_size(compressed) == len(slp)
+
+
+def test_uncompressed_sections():
+    compressed = slpz.compress(make_slp())
+    game_start = slpz.game_start(compressed)
+    assert game_start[0] == GAME_START
+    assert len(game_start) == SIZES[GAME_START] + 1
+    assert slpz.metadata(compressed) == METADATA
+
+
+def test_errors():
+    with pytest.raises(slpz.CompError):
+        slpz.compress(b"not a replay")
+    with pytest.raises(slpz.DecompError):
+        slpz.decompress(make_slp())
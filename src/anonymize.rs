@@ -0,0 +1,158 @@
+//! Replaces personal information in replays with pseudonyms.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{game_start, ubjson, EditError};
+
+/// Replaces player names and connect codes with pseudonyms, and removes other personal information.
+///
+/// In Game Start, nametags and Slippi user IDs are cleared, 
+/// and display names and connect codes are replaced.
+/// In metadata, player names and codes are replaced, other player names are removed,
+/// and `consoleNick` is removed.
+///
+/// Pseudonyms are derived from each player's connect code, or their name without one,
+/// so a player has the same pseudonym in every replay anonymized with the same `salt`.
+/// Use a secret salt for published datasets, 
+/// otherwise pseudonyms can be matched to players by anonymizing their known codes.
+/// The pseudonyms are not cryptographically secure.
+pub fn anonymize(file: &[u8], salt: &[u8]) -> Result<Vec<u8>, EditError> {
+    crate::edit_sections(file, |gs, metadata| {
+        for port in 0..4 {
+            let key = format!("players.{}.names", port);
+            let metadata_names = metadata.path(&key);
+            let metadata_name = |name: &str| metadata_names.and_then(|n| n.get(name)).and_then(|n| n.as_str());
+
+            // the most stable identity available
+            let code = game_start::connect_code(gs, port).filter(|c| !c.is_empty());
+            let identity = match code {
+                Some(code) => Some(code),
+                None => metadata_name("code")
+                    .or_else(|| metadata_name("netplay"))
+                    .filter(|n| !n.is_empty())
+                    .map(String::from)
+                    .or_else(|| game_start::display_name(gs, port)
+                        .filter(|n| !n.is_empty())
+                        .map(|n| String::from_utf8_lossy(n).into_owned())),
+            };
+
+            game_start::clear_player_names(gs, port);
+            let Some(identity) = identity else {
+                metadata.remove_path(&key);
+                continue;
+            };
+
+            let (name, code) = pseudonym(salt, &identity);
+            game_start::set_display_name(gs, port, &name);
+            game_start::set_connect_code(gs, port, &code);
+
+            let mut names = ubjson::Value::Object(Vec::new());
+            names.insert("netplay", ubjson::Value::String(name));
+            names.insert("code", ubjson::Value::String(code));
+            // only replaces names if the player is in the metadata
+            if let Some(player) = metadata.path_mut(&format!("players.{}", port)) {
+                player.insert("names", names);
+            }
+        }
+
+        metadata.remove("consoleNick");
+    })
+}
+
+/// Returns a display name and connect code.
+fn pseudonym(salt: &[u8], identity: &str) -> (String, String) {
+    // FNV-1a
+    let mut hash = 0xcbf29ce484222325u64;
+    for &b in salt.iter().chain(&[0]).chain(identity.as_bytes()) {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    let mut letters = String::new();
+    for _ in 0..4 {
+        letters.push((b'A' + (hash % 26) as u8) as char);
+        hash /= 26;
+    }
+    let digits = hash % 1000;
+
+    (format!("Anon {}{:03}", letters, digits), format!("{}#{:03}", letters, digits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay;
+    use crate::{compress, decompress, Compressor, Decompressor};
+
+    /// The fixture replay with a netplay player in port 1, a local player in port 2, and empty ports 3 and 4.
+    fn named_replay() -> Vec<u8> {
+        crate::edit_sections(&replay(-123, 50), |gs, metadata| {
+            for port in 0..4 { game_start::clear_player_names(gs, port) }
+            game_start::set_display_name(gs, 0, "Player One");
+            game_start::set_connect_code(gs, 0, "ONE#123");
+            gs[0x161..0x165].copy_from_slice(b"TAG1");
+            gs[0x249..0x253].copy_from_slice(b"secret-uid");
+            game_start::set_display_name(gs, 1, "Couch Two");
+
+            metadata.insert_path("players.0.names.netplay", ubjson::Value::String("Player One".into()));
+            metadata.insert_path("players.0.names.code", ubjson::Value::String("ONE#123".into()));
+            metadata.insert_path("players.1.names.netplay", ubjson::Value::String("Couch Two".into()));
+            metadata.insert("consoleNick", ubjson::Value::String("Home Wii".into()));
+        }).unwrap()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    /// The Game Start display name and connect code, and the metadata code of a port.
+    type Names = (Option<Vec<u8>>, Option<String>, Option<String>);
+
+    fn names(file: &[u8]) -> Vec<Names> {
+        let sections = crate::sections(file).unwrap();
+        let metadata = ubjson::read_metadata(sections.metadata).unwrap();
+        (0..4).map(|port| (
+            game_start::display_name(sections.game_start, port).map(<[u8]>::to_vec),
+            game_start::connect_code(sections.game_start, port),
+            metadata.path(&format!("players.{}.names.code", port)).and_then(|c| c.as_str()).map(String::from),
+        )).collect()
+    }
+
+    #[test]
+    fn pseudonyms() {
+        let slp = named_replay();
+        let anonymized = anonymize(&slp, b"salt").unwrap();
+        assert_eq!(anonymized, anonymize(&slp, b"salt").unwrap());
+
+        for personal in [&b"Player One"[..], b"ONE", b"TAG1", b"secret-uid", b"Couch Two", b"Home Wii", b"consoleNick"] {
+            assert!(!contains(&anonymized, personal), "{}", String::from_utf8_lossy(personal));
+        }
+
+        let (name, code) = pseudonym(b"salt", "ONE#123");
+        let players = names(&anonymized);
+        assert_eq!(players[0], (Some(name.clone().into_bytes()), Some(code.clone()), Some(code)));
+        let metadata = ubjson::read_metadata(crate::sections(&anonymized).unwrap().metadata).unwrap();
+        assert_eq!(metadata.path("players.0.names.netplay").and_then(|n| n.as_str()), Some(name.as_str()));
+
+        // players without a code are named from their display name
+        let (name, code) = pseudonym(b"salt", "Couch Two");
+        assert_eq!(players[1], (Some(name.into_bytes()), Some(code.clone()), Some(code)));
+        assert_eq!(players[2], (Some(Vec::new()), Some(String::new()), None));
+        assert_eq!(players[3], (Some(Vec::new()), Some(String::new()), None));
+
+        let other = names(&anonymize(&slp, b"other salt").unwrap());
+        assert_ne!(other[0], players[0]);
+        assert_ne!(other[1], players[1]);
+    }
+
+    #[test]
+    fn slp_and_slpz() {
+        let slp = named_replay();
+        let slpz = compress(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        let anonymized = anonymize(&slpz, b"salt").unwrap();
+        assert_eq!(crate::detect_format(&anonymized), crate::Format::Slpz);
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &anonymized).unwrap(), anonymize(&slp, b"salt").unwrap());
+    }
+}
@@ -0,0 +1,483 @@
+//! The slpzar format. Packs many replays into a single file.
+//!
+//! All integers are big endian.
+//!
+//! - Header (32 bytes)
+//!     - magic (8 bytes): `slpzar\0\0`
+//!     - version (u32)
+//!     - dictionary size (u32)
+//!     - index offset (u64)
+//!     - index size (u64)
+//! - zstd dictionary. May be empty.
+//! - Entries. Each entry is an slpz file compressed with the dictionary.
+//! - Index
+//!     - entry count (u64)
+//!     - for each entry:
+//!         - name size (u16), name (utf8, relative path separated with '/')
+//!         - offset (u64), size (u64) of the slpz file
+//!         - size of the slp file (u64)
+//!         - Game Start size (u32), Game Start, including the command byte
+//!         - metadata size (u32), metadata
+//!
+//! Game Start and metadata are copied into the index, so archives can be listed without reading entries.
+//! An index size of 0 means the archive is empty.
+//!
+//! Appending writes new entries and a new index after the old index, then updates the header.
+//! Until the header is updated the old index is still used, so an interrupted append loses nothing.
+//! Old indexes are left in the file as unused space, so every append grows the archive by the size of its index.
+//! `compact_archive` rewrites an archive without them.
+
+use crate::{Compressor, Decompressor, CompError, DecompError, Header};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const MAGIC: [u8; 8] = *b"slpzar\0\0";
+const ARCHIVE_HEADER_SIZE: usize = 32;
+pub const ARCHIVE_VERSION: u32 = 0;
+
+/// The default maximum dictionary size for `train_dictionary`.
+pub const DICTIONARY_SIZE: usize = 110 * 1024;
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Io(std::io::Error),
+    InvalidArchive,
+    Compress(CompError),
+    Decompress(DecompError),
+    /// An entry with the same name is already in the archive.
+    DuplicateName,
+    /// Names must be relative paths separated with '/', without '.' or '..' components.
+    InvalidName,
+    TrainingFailure,
+    ZstdInitError,
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveError::Io(e) => write!(f, "{}", e),
+            ArchiveError::InvalidArchive => write!(f, "Archive is invalid"),
+            ArchiveError::Compress(e) => write!(f, "{}", e),
+            ArchiveError::Decompress(e) => write!(f, "{}", e),
+            ArchiveError::DuplicateName => write!(f, "Name is already in the archive"),
+            ArchiveError::InvalidName => write!(f, "Name is invalid"),
+            ArchiveError::TrainingFailure => write!(f, "Failed to train dictionary"),
+            ArchiveError::ZstdInitError => write!(f, "Failed to init zstd"),
+        }
+    }
+}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(e: std::io::Error) -> Self { ArchiveError::Io(e) }
+}
+
+/// A replay in an archive.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArchiveEntry {
+    pub name: String,
+    /// Offset of the slpz file from archive start.
+    pub offset: u64,
+    /// Size of the slpz file.
+    pub size: u64,
+    /// Size of the slp file.
+    pub decompressed_size: u64,
+    /// The uncompressed Game Start event, including the command byte.
+    pub game_start: Vec<u8>,
+    /// The uncompressed metadata. See `Header::metadata_offset`.
+    pub metadata: Vec<u8>,
+}
+
+/// An slpzar file opened for reading.
+pub struct Archive {
+    file: std::fs::File,
+    dictionary: Vec<u8>,
+    entries: Vec<ArchiveEntry>,
+}
+
+impl Archive {
+    /// Opens an archive and reads its index.
+    pub fn open(path: &std::path::Path) -> Result<Archive, ArchiveError> {
+        let mut file = std::fs::File::open(path)?;
+        let (dictionary, entries) = read_archive(&mut file)?;
+        Ok(Archive { file, dictionary, entries })
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] { &self.entries }
+
+    /// The zstd dictionary. Empty if the archive does not have one.
+    pub fn dictionary(&self) -> &[u8] { &self.dictionary }
+
+    /// Returns the index of the entry with this name.
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.name == name)
+    }
+
+    /// Returns a decompressor for the entries of this archive.
+    pub fn decompressor(&self) -> Option<Decompressor> {
+        match self.dictionary.is_empty() {
+            true => Decompressor::new(),
+            false => Decompressor::with_dictionary(&self.dictionary),
+        }
+    }
+
+    /// Reads the slpz file of an entry.
+    ///
+    /// If the archive has a dictionary, it is needed to decompress the slpz file.
+    /// Panics if `i` is out of bounds.
+    pub fn read_slpz(&self, i: usize) -> Result<Vec<u8>, ArchiveError> {
+        let entry = &self.entries[i];
+        let mut slpz = vec![0u8; entry.size as usize];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        file.read_exact(&mut slpz)?;
+        Ok(slpz)
+    }
+
+    /// Reads and decompresses the slp file of an entry.
+    ///
+    /// `decompressor` must come from `Archive::decompressor`.
+    /// Panics if `i` is out of bounds.
+    pub fn extract(&self, decompressor: &mut Decompressor, i: usize) -> Result<Vec<u8>, ArchiveError> {
+        let slpz = self.read_slpz(i)?;
+        // sized from the slpz header rather than the index, which may be corrupt
+        let mut slp = Vec::new();
+        decompressor.decompress_into(&slpz, &mut slp).map_err(ArchiveError::Decompress)?;
+        Ok(slp)
+    }
+}
+
+/// Writes a new slpzar file, or appends to an existing one.
+///
+/// Entries are only added to the index by `finish`.
+/// If the writer is dropped or the program crashes before that, 
+/// the archive is empty or, when appending, unchanged.
+pub struct ArchiveWriter {
+    file: std::fs::File,
+    compressor: Compressor,
+    entries: Vec<ArchiveEntry>,
+    names: std::collections::HashSet<String>,
+    /// Where the next entry is written.
+    end: u64,
+    slpz: Vec<u8>,
+}
+
+impl ArchiveWriter {
+    /// Creates an archive, replacing any file at `path`.
+    ///
+    /// compression_level should be between 1..=19. The default is 3.
+    pub fn create(
+        path: &std::path::Path,
+        compression_level: i32,
+        dictionary: Option<&[u8]>,
+    ) -> Result<ArchiveWriter, ArchiveError> {
+        let dictionary = dictionary.unwrap_or(&[]);
+        if dictionary.len() > u32::MAX as usize { return Err(ArchiveError::InvalidArchive) }
+        let compressor = match dictionary.is_empty() {
+            true => Compressor::new(compression_level),
+            false => Compressor::with_dictionary(compression_level, dictionary),
+        }.ok_or(ArchiveError::ZstdInitError)?;
+
+        let mut file = std::fs::File::create(path)?;
+        let mut header = [0u8; ARCHIVE_HEADER_SIZE];
+        header[0..8].copy_from_slice(&MAGIC);
+        header[8..12].copy_from_slice(&ARCHIVE_VERSION.to_be_bytes());
+        header[12..16].copy_from_slice(&(dictionary.len() as u32).to_be_bytes());
+        file.write_all(&header)?;
+        file.write_all(dictionary)?;
+
+        Ok(ArchiveWriter {
+            file,
+            compressor,
+            entries: Vec::new(),
+            names: std::collections::HashSet::new(),
+            end: (ARCHIVE_HEADER_SIZE + dictionary.len()) as u64,
+            slpz: Vec::new(),
+        })
+    }
+
+    /// Opens an existing archive to add entries to.
+    ///
+    /// Entries are compressed with the archive's dictionary, if it has one.
+    /// compression_level should be between 1..=19. The default is 3.
+    ///
+    /// The old index is left in the archive as unused space. See `compact_archive`.
+    pub fn append(path: &std::path::Path, compression_level: i32) -> Result<ArchiveWriter, ArchiveError> {
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+        let (dictionary, entries) = read_archive(&mut file)?;
+        let compressor = match dictionary.is_empty() {
+            true => Compressor::new(compression_level),
+            false => Compressor::with_dictionary(compression_level, &dictionary),
+        }.ok_or(ArchiveError::ZstdInitError)?;
+
+        // after the old index and anything left by interrupted appends
+        let end = file.metadata()?.len();
+
+        Ok(ArchiveWriter {
+            file,
+            compressor,
+            names: entries.iter().map(|e| e.name.clone()).collect(),
+            entries,
+            end,
+            slpz: Vec::new(),
+        })
+    }
+
+    /// The entries in the archive, including those added so far.
+    pub fn entries(&self) -> &[ArchiveEntry] { &self.entries }
+
+    /// Compresses an slp file and writes it to the archive.
+    ///
+    /// `name` must be a relative path separated with '/', without '.' or '..' components.
+    pub fn add(&mut self, name: &str, slp: &[u8]) -> Result<(), ArchiveError> {
+        if !valid_name(name) { return Err(ArchiveError::InvalidName) }
+        if self.names.contains(name) { return Err(ArchiveError::DuplicateName) }
+
+        self.compressor.compress_into(slp, &mut self.slpz).map_err(ArchiveError::Compress)?;
+        let header = Header::read(&self.slpz).map_err(|_| ArchiveError::Compress(CompError::CompressionFailure))?;
+
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&self.slpz)?;
+
+        self.entries.push(ArchiveEntry {
+            name: name.to_string(),
+            offset: self.end,
+            size: self.slpz.len() as u64,
+            decompressed_size: header.decompressed_size() as u64,
+            game_start: self.slpz[header.game_start_offset..header.metadata_offset].to_vec(),
+            metadata: self.slpz[header.metadata_offset..header.compressed_events_offset].to_vec(),
+        });
+        self.names.insert(name.to_string());
+        self.end += self.slpz.len() as u64;
+
+        Ok(())
+    }
+
+    /// Writes the index after the entries, then points the header at it.
+    pub fn finish(mut self) -> Result<(), ArchiveError> {
+        let mut index = Vec::new();
+        index.extend_from_slice(&(self.entries.len() as u64).to_be_bytes());
+        for e in self.entries.iter() {
+            index.extend_from_slice(&(e.name.len() as u16).to_be_bytes());
+            index.extend_from_slice(e.name.as_bytes());
+            index.extend_from_slice(&e.offset.to_be_bytes());
+            index.extend_from_slice(&e.size.to_be_bytes());
+            index.extend_from_slice(&e.decompressed_size.to_be_bytes());
+            index.extend_from_slice(&(e.game_start.len() as u32).to_be_bytes());
+            index.extend_from_slice(&e.game_start);
+            index.extend_from_slice(&(e.metadata.len() as u32).to_be_bytes());
+            index.extend_from_slice(&e.metadata);
+        }
+
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&index)?;
+        self.file.set_len(self.end + index.len() as u64)?;
+        self.file.sync_data()?;
+
+        // the header is written last, so the previous index stays valid if writing the new one fails
+        let mut index_location = [0u8; 16];
+        index_location[0..8].copy_from_slice(&self.end.to_be_bytes());
+        index_location[8..16].copy_from_slice(&(index.len() as u64).to_be_bytes());
+        self.file.seek(SeekFrom::Start(16))?;
+        self.file.write_all(&index_location)?;
+        self.file.sync_data()?;
+
+        Ok(())
+    }
+}
+
+/// Rewrites an archive without the unused space left by appends.
+///
+/// Entries are copied without recompressing them. The new archive is written next to the old one 
+/// and then renamed over it, so an interrupted compaction leaves the old archive unchanged.
+pub fn compact_archive(path: &std::path::Path) -> Result<(), ArchiveError> {
+    let archive = Archive::open(path)?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = std::path::PathBuf::from(tmp);
+
+    let copy = |tmp: &std::path::Path| -> Result<(), ArchiveError> {
+        let mut writer = ArchiveWriter::create(tmp, 3, Some(archive.dictionary()))?;
+        for (i, entry) in archive.entries().iter().enumerate() {
+            let slpz = archive.read_slpz(i)?;
+            writer.file.write_all(&slpz)?;
+            writer.entries.push(ArchiveEntry { offset: writer.end, ..entry.clone() });
+            writer.end += slpz.len() as u64;
+        }
+        writer.finish()?;
+        std::fs::set_permissions(tmp, std::fs::metadata(path)?.permissions())?;
+        Ok(())
+    };
+
+    if let Err(e) = copy(&tmp) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e)
+    }
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Trains a zstd dictionary for `ArchiveWriter::create` from a sample of slp files.
+///
+/// `max_size` is usually `DICTIONARY_SIZE`. Invalid replays are skipped.
+/// Training needs a reasonable number of replays, a few dozen at least.
+///
+/// Replays are taken from `slps` one at a time until 64MiB of samples are gathered,
+/// so they can be read lazily instead of all at once.
+pub fn train_dictionary<S: AsRef<[u8]>>(
+    slps: impl IntoIterator<Item = S>, 
+    max_size: usize,
+) -> Result<Vec<u8>, ArchiveError> {
+    // zstd trains on many small samples better than a few large ones
+    const SAMPLE_SIZE: usize = 128 * 1024;
+    const MAX_SAMPLES_SIZE: usize = 64 * 1024 * 1024;
+
+    let mut compressor = Compressor::new(3).ok_or(ArchiveError::ZstdInitError)?;
+    let mut sections = Vec::new();
+    let mut samples = Vec::new();
+    let mut sample_sizes = Vec::new();
+
+    'replays: for slp in slps {
+        if compressor.write_sections(slp.as_ref(), &mut sections).is_err() { continue }
+        for sample in compressor.reordered.chunks(SAMPLE_SIZE) {
+            if samples.len() + sample.len() > MAX_SAMPLES_SIZE { break 'replays }
+            samples.extend_from_slice(sample);
+            sample_sizes.push(sample.len());
+        }
+    }
+
+    let mut dictionary = Vec::with_capacity(max_size);
+    zstd_safe::train_from_buffer(&mut dictionary, &samples, &sample_sizes)
+        .map_err(|_| ArchiveError::TrainingFailure)?;
+    Ok(dictionary)
+}
+
+/// Returns the dictionary and the entries.
+fn read_archive(file: &mut std::fs::File) -> Result<(Vec<u8>, Vec<ArchiveEntry>), ArchiveError> {
+    let file_len = file.metadata()?.len();
+
+    let mut header = [0u8; ARCHIVE_HEADER_SIZE];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header).map_err(|_| ArchiveError::InvalidArchive)?;
+    if header[0..8] != MAGIC { return Err(ArchiveError::InvalidArchive) }
+    let version = u32::from_be_bytes(header[8..12].try_into().unwrap());
+    if version > ARCHIVE_VERSION { return Err(ArchiveError::InvalidArchive) }
+    let dictionary_size = u32::from_be_bytes(header[12..16].try_into().unwrap()) as u64;
+    let index_offset = u64::from_be_bytes(header[16..24].try_into().unwrap());
+    let index_size = u64::from_be_bytes(header[24..32].try_into().unwrap());
+
+    let entries_offset = ARCHIVE_HEADER_SIZE as u64 + dictionary_size;
+    if entries_offset > file_len { return Err(ArchiveError::InvalidArchive) }
+    let mut dictionary = vec![0u8; dictionary_size as usize];
+    file.read_exact(&mut dictionary)?;
+
+    if index_size == 0 { return Ok((dictionary, Vec::new())) }
+
+    let index_in_file = entries_offset <= index_offset
+        && index_offset.checked_add(index_size).is_some_and(|end| end <= file_len);
+    if !index_in_file { return Err(ArchiveError::InvalidArchive) }
+    let mut index = vec![0u8; index_size as usize];
+    file.seek(SeekFrom::Start(index_offset))?;
+    file.read_exact(&mut index)?;
+
+    let entries = parse_index(&index, entries_offset, index_offset).ok_or(ArchiveError::InvalidArchive)?;
+    Ok((dictionary, entries))
+}
+
+/// Entries must lie between `entries_offset` and `index_offset`.
+fn parse_index(mut index: &[u8], entries_offset: u64, index_offset: u64) -> Option<Vec<ArchiveEntry>> {
+    fn take<'a>(index: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+        if index.len() < n { return None }
+        let (bytes, rest) = index.split_at(n);
+        *index = rest;
+        Some(bytes)
+    }
+    fn u16(index: &mut &[u8]) -> Option<u16> { Some(u16::from_be_bytes(take(index, 2)?.try_into().unwrap())) }
+    fn u32(index: &mut &[u8]) -> Option<u32> { Some(u32::from_be_bytes(take(index, 4)?.try_into().unwrap())) }
+    fn u64(index: &mut &[u8]) -> Option<u64> { Some(u64::from_be_bytes(take(index, 8)?.try_into().unwrap())) }
+
+    let count = u64(&mut index)?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let name_size = u16(&mut index)? as usize;
+        let name = std::str::from_utf8(take(&mut index, name_size)?).ok()?.to_string();
+        let offset = u64(&mut index)?;
+        let size = u64(&mut index)?;
+        let decompressed_size = u64(&mut index)?;
+        let game_start_size = u32(&mut index)? as usize;
+        let game_start = take(&mut index, game_start_size)?.to_vec();
+        let metadata_size = u32(&mut index)? as usize;
+        let metadata = take(&mut index, metadata_size)?.to_vec();
+
+        if !valid_name(&name) { return None }
+        if offset < entries_offset || offset.checked_add(size)? > index_offset { return None }
+
+        entries.push(ArchiveEntry { name, offset, size, decompressed_size, game_start, metadata });
+    }
+
+    match index.is_empty() {
+        true => Some(entries),
+        false => None,
+    }
+}
+
+/// Names are joined to directories when unpacking, so they must not escape them.
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= u16::MAX as usize
+        && !name.contains('\\')
+        && !name.contains(':')
+        && name.split('/').all(|c| !c.is_empty() && c != "." && c != "..")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay;
+
+    fn extract_all(path: &std::path::Path) -> Vec<(String, Vec<u8>)> {
+        let archive = Archive::open(path).unwrap();
+        let mut decompressor = archive.decompressor().unwrap();
+        archive.entries().iter().enumerate()
+            .map(|(i, e)| (e.name.clone(), archive.extract(&mut decompressor, i).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn append_and_compact() {
+        let path = std::env::temp_dir().join(format!("slpz-archive-test-{}.slpzar", std::process::id()));
+        let replays = [("a.slp", replay(-123, 50)), ("dir/b.slp", replay(0, 10)), ("c.slp", replay(5, 300))];
+
+        let mut writer = ArchiveWriter::create(&path, 3, None).unwrap();
+        writer.add(replays[0].0, &replays[0].1).unwrap();
+        writer.add(replays[1].0, &replays[1].1).unwrap();
+        assert!(matches!(writer.add("a.slp", &replays[0].1), Err(ArchiveError::DuplicateName)));
+        assert!(matches!(writer.add("../a.slp", &replays[0].1), Err(ArchiveError::InvalidName)));
+        writer.finish().unwrap();
+
+        // an append that is never finished leaves the archive as it was
+        let mut writer = ArchiveWriter::append(&path, 3).unwrap();
+        writer.add(replays[2].0, &replays[2].1).unwrap();
+        drop(writer);
+        assert_eq!(extract_all(&path).len(), 2);
+
+        let mut writer = ArchiveWriter::append(&path, 3).unwrap();
+        assert!(matches!(writer.add("dir/b.slp", &replays[1].1), Err(ArchiveError::DuplicateName)));
+        writer.add(replays[2].0, &replays[2].1).unwrap();
+        writer.finish().unwrap();
+
+        let expected = replays.iter().map(|(n, slp)| (n.to_string(), slp.clone())).collect::<Vec<_>>();
+        assert_eq!(extract_all(&path), expected);
+
+        let size = std::fs::metadata(&path).unwrap().len();
+        compact_archive(&path).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() < size);
+        assert_eq!(extract_all(&path), expected);
+
+        // a corrupt index size is not allocated
+        let mut archive = Archive::open(&path).unwrap();
+        archive.entries[0].decompressed_size = u64::MAX;
+        let mut decompressor = archive.decompressor().unwrap();
+        assert_eq!(archive.extract(&mut decompressor, 0).unwrap(), expected[0].1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,344 @@
+//! Walks the events of a replay in their original order.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::{
+    detect_format, event_sizes, Decompressor, DecompError, Format, Header, SlpLayout,
+    PRE_FRAME_UPDATE, POST_FRAME_UPDATE, FRAME_START, ITEM_UPDATE, FRAME_BOOKEND,
+};
+
+/// The events after Game Start of an slp or slpz file, in their original order. See `events`.
+pub struct Events<'a> {
+    event_sizes: [u16; 256],
+    source: Source<'a>,
+    /// Payloads gathered from the reordered events of slpz files.
+    buf: Vec<u8>,
+}
+
+enum Source<'a> {
+    /// Events of an slp file, in order.
+    Slp { events: &'a [u8], i: usize },
+    /// Reordered events of an slpz file, as written by `reorder_events`.
+    Reordered { event_order_list: &'a [u8], data: &'a [u8], columns: Box<Columns>, i: usize },
+}
+
+struct Columns {
+    /// Start of each command's bytes in the reordered data.
+    offsets: [u32; 256],
+    /// Events of each command, which is also the stride between a command's bytes.
+    counts: [u32; 256],
+    /// Events of each command already read.
+    read: [u32; 256],
+}
+
+/// The events of a single frame, returned by `Events::next_frame`.
+#[derive(Copy, Clone, Debug)]
+pub struct Frame<'a> {
+    /// None for events outside of frames, such as the Gecko code list and Game End.
+    pub number: Option<i32>,
+    /// The events as in an slp file, each a command byte followed by its payload.
+    pub bytes: &'a [u8],
+    event_sizes: &'a [u16; 256],
+}
+
+/// Returns the events after Game Start of an slp or slpz file.
+///
+/// slpz files are decompressed into the decompressor's scratch space,
+/// but are never unordered into a full slp file.
+pub fn events<'a>(decompressor: &'a mut Decompressor, file: &'a [u8]) -> Result<Events<'a>, DecompError> {
+    match detect_format(file) {
+        Format::Slp => {
+            let layout = SlpLayout::read(file).map_err(|_| DecompError::InvalidFile)?;
+            let events = &file[layout.game_start_offset + layout.game_start_size..layout.metadata_offset];
+
+            // check every event fits, so reading never fails later
+            let mut i = 0;
+            while i < events.len() {
+                let size = layout.event_sizes[events[i] as usize];
+                if size == 0 { return Err(DecompError::InvalidFile) }
+                i += 1 + size as usize;
+            }
+            if i != events.len() { return Err(DecompError::InvalidFile) }
+
+            Ok(Events { event_sizes: layout.event_sizes, source: Source::Slp { events, i: 0 }, buf: Vec::new() })
+        }
+        Format::Slpz => {
+            let header = Header::read(file)?;
+            let (event_sizes, _) = event_sizes(&file[header.event_sizes_offset..header.game_start_offset])
+                .ok_or(DecompError::InvalidFile)?;
+            decompressor.decompress_events(file, &header)?;
+            let reordered: &'a [u8] = &decompressor.reordered;
+
+            let total_events = u32::from_be_bytes(reordered.get(0..4).ok_or(DecompError::InvalidFile)?.try_into().unwrap());
+            let event_order_list = reordered.get(4..4 + total_events as usize).ok_or(DecompError::InvalidFile)?;
+            let data = &reordered[4 + total_events as usize..];
+
+            let mut counts = [0u32; 256];
+            for &command in event_order_list { counts[command as usize] += 1 }
+
+            let mut offsets = [0u32; 256];
+            let mut size = 0usize;
+            for command in 0..256 {
+                offsets[command] = size as u32;
+                size += event_sizes[command] as usize * counts[command] as usize;
+                if counts[command] > 0 && event_sizes[command] == 0 { return Err(DecompError::InvalidFile) }
+            }
+            if size != data.len() { return Err(DecompError::InvalidFile) }
+
+            Ok(Events {
+                event_sizes,
+                source: Source::Reordered { 
+                    event_order_list, 
+                    data, 
+                    columns: Box::new(Columns { offsets, counts, read: [0; 256] }), 
+                    i: 0,
+                },
+                buf: Vec::new(),
+            })
+        }
+        Format::Unknown => Err(DecompError::InvalidFile),
+    }
+}
+
+impl<'a> Events<'a> {
+    /// The payload size of each command, from the Event Payloads event.
+    pub fn event_sizes(&self) -> &[u16; 256] { &self.event_sizes }
+
+    /// Returns the next command and its payload.
+    ///
+    /// This is not an `Iterator`, as payloads of slpz files are gathered into a buffer
+    /// that is reused for every event.
+    pub fn next_event(&mut self) -> Option<(u8, &[u8])> {
+        let command = self.peek()?;
+        self.buf.clear();
+        let range = self.read_event()?;
+        let bytes = match &self.source {
+            Source::Slp { events, .. } => &events[range],
+            Source::Reordered { .. } => &self.buf[..],
+        };
+        Some((command, &bytes[1..]))
+    }
+
+    /// Returns the events of the next frame.
+    ///
+    /// A frame starts at Frame Start and ends after Frame Bookend.
+    /// Replays from before Frame Start and Frame Bookend were added are split where the frame number changes,
+    /// so events after their last frame, such as Game End, are part of it.
+    /// Events between frames are returned together, with no frame number.
+    pub fn next_frame(&mut self) -> Option<Frame<'_>> {
+        let first = self.peek()?;
+        let number = self.peek_frame_number();
+        self.buf.clear();
+        let mut range = self.read_event()?;
+
+        if number.is_some() && first != FRAME_BOOKEND {
+            while let Some(command) = self.peek() {
+                let next_number = self.peek_frame_number();
+                let ends = command == FRAME_START || next_number.is_some_and(|n| Some(n) != number);
+                if ends { break }
+
+                range.end = self.read_event()?.end;
+                if command == FRAME_BOOKEND { break }
+            }
+        } else if number.is_none() {
+            while self.peek().is_some() && self.peek_frame_number().is_none() {
+                range.end = self.read_event()?.end;
+            }
+        }
+
+        let bytes = match &self.source {
+            Source::Slp { events, .. } => &events[range],
+            Source::Reordered { .. } => &self.buf[..],
+        };
+        Some(Frame { number, bytes, event_sizes: &self.event_sizes })
+    }
+
+    /// Moves past the next event without reading its payload.
+    fn skip_event(&mut self) {
+        let Some(command) = self.peek() else { return };
+        match &mut self.source {
+            Source::Slp { i, .. } => *i += 1 + self.event_sizes[command as usize] as usize,
+            Source::Reordered { columns, i, .. } => {
+                columns.read[command as usize] += 1;
+                *i += 1;
+            }
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        match &self.source {
+            Source::Slp { events, i } => events.get(*i).copied(),
+            Source::Reordered { event_order_list, i, .. } => event_order_list.get(*i).copied(),
+        }
+    }
+
+    /// The frame number of the next event, if it is a frame event.
+    fn peek_frame_number(&self) -> Option<i32> {
+        let command = self.peek()?;
+        if !matches!(command, PRE_FRAME_UPDATE | POST_FRAME_UPDATE | FRAME_START | ITEM_UPDATE | FRAME_BOOKEND) {
+            return None
+        }
+        if self.event_sizes[command as usize] < 4 { return None }
+
+        let mut frame = [0u8; 4];
+        match &self.source {
+            Source::Slp { events, i } => frame.copy_from_slice(&events[i + 1..][..4]),
+            Source::Reordered { data, columns, .. } => {
+                let c = command as usize;
+                let Columns { offsets, counts, read } = &**columns;
+                for (j, b) in frame.iter_mut().enumerate() {
+                    *b = data[offsets[c] as usize + j * counts[c] as usize + read[c] as usize];
+                }
+            }
+        }
+        Some(i32::from_be_bytes(frame))
+    }
+
+    /// Moves past the next event.
+    /// For slp files, returns its range in the events.
+    /// For slpz files, appends it to `self.buf` and returns its range there.
+    fn read_event(&mut self) -> Option<core::ops::Range<usize>> {
+        let command = self.peek()?;
+        let size = self.event_sizes[command as usize] as usize;
+        match &mut self.source {
+            Source::Slp { i, .. } => {
+                let start = *i;
+                *i += 1 + size;
+                Some(start..*i)
+            }
+            Source::Reordered { data, columns, i, .. } => {
+                let c = command as usize;
+                let start = self.buf.len();
+                self.buf.push(command);
+                let column = columns.offsets[c] as usize + columns.read[c] as usize;
+                let stride = columns.counts[c] as usize;
+                self.buf.extend((0..size).map(|j| data[column + j * stride]));
+
+                columns.read[c] += 1;
+                *i += 1;
+                Some(start..self.buf.len())
+            }
+        }
+    }
+}
+
+/// Events of some commands, returned by `decode_commands`.
+#[derive(Clone, Debug)]
+pub struct SelectedEvents {
+    bytes: Vec<u8>,
+    event_sizes: [u16; 256],
+}
+
+impl SelectedEvents {
+    /// The events in their original order, each a command byte followed by its payload.
+    pub fn bytes(&self) -> &[u8] { &self.bytes }
+
+    /// The payload size of each command, from the Event Payloads event.
+    pub fn event_sizes(&self) -> &[u16; 256] { &self.event_sizes }
+
+    /// Returns each command and its payload.
+    pub fn events(&self) -> EventIter<'_> {
+        EventIter { bytes: &self.bytes, event_sizes: &self.event_sizes }
+    }
+}
+
+/// Returns only the events of some commands of an slp or slpz file, such as Pre-Frame Updates (0x37).
+///
+/// slpz files store each command's payloads together, so only those payloads are gathered,
+/// in the order recovered from the event order list. The events are still fully decompressed.
+pub fn decode_commands(
+    decompressor: &mut Decompressor, 
+    file: &[u8], 
+    commands: &[u8],
+) -> Result<SelectedEvents, DecompError> {
+    let mut selected = [false; 256];
+    for &command in commands { selected[command as usize] = true }
+
+    let mut events = events(decompressor, file)?;
+    let mut bytes = Vec::new();
+    while let Some(command) = events.peek() {
+        if !selected[command as usize] {
+            events.skip_event();
+            continue
+        }
+
+        // events of slpz files are gathered into events.buf
+        let range = events.read_event().ok_or(DecompError::InvalidFile)?;
+        if let Source::Slp { events, .. } = &events.source { bytes.extend_from_slice(&events[range]) }
+    }
+    if let Source::Reordered { .. } = events.source { bytes = events.buf }
+
+    Ok(SelectedEvents { bytes, event_sizes: events.event_sizes })
+}
+
+impl<'a> Frame<'a> {
+    /// Returns each command and its payload.
+    pub fn events(&self) -> EventIter<'a> {
+        EventIter { bytes: self.bytes, event_sizes: self.event_sizes }
+    }
+}
+
+/// Iterates over events laid out as in an slp file. See `Frame::events`.
+pub struct EventIter<'a> {
+    bytes: &'a [u8],
+    event_sizes: &'a [u16; 256],
+}
+
+impl<'a> Iterator for EventIter<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&command, rest) = self.bytes.split_first()?;
+        let size = self.event_sizes[command as usize] as usize;
+
+        // `Frame::bytes` can be changed, so a truncated event ends iteration
+        let Some(payload) = rest.get(..size) else {
+            self.bytes = &[];
+            return None
+        };
+        self.bytes = &rest[size..];
+        Some((command, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_commands_slp_and_slpz() {
+        let slp = crate::tests::replay(-123, 100);
+        let slpz = crate::compress(&mut crate::Compressor::new(3).unwrap(), &slp).unwrap();
+        let mut decompressor = Decompressor::new().unwrap();
+
+        for commands in [&[PRE_FRAME_UPDATE][..], &[POST_FRAME_UPDATE, FRAME_BOOKEND], &[0x10], &[]] {
+            let from_slp = decode_commands(&mut decompressor, &slp, commands).unwrap();
+            let from_slpz = decode_commands(&mut decompressor, &slpz, commands).unwrap();
+            assert_eq!(from_slp.bytes(), from_slpz.bytes());
+            assert_eq!(from_slp.event_sizes(), from_slpz.event_sizes());
+
+            let events = from_slpz.events().collect::<Vec<_>>();
+            assert_eq!(events.len(), 224 * commands.iter().filter(|&&c| c != 0x10).count());
+            assert!(events.iter().all(|(command, _)| commands.contains(command)));
+        }
+
+        let pre_frames = decode_commands(&mut decompressor, &slpz, &[PRE_FRAME_UPDATE]).unwrap();
+        let frames = pre_frames.events()
+            .map(|(_, payload)| i32::from_be_bytes(payload[..4].try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(frames, (-123..=100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let slp = crate::tests::replay(0, 2);
+        let mut decompressor = Decompressor::new().unwrap();
+        let mut events = events(&mut decompressor, &slp).unwrap();
+        let mut frame = events.next_frame().unwrap();
+        assert_eq!(frame.events().count(), 4);
+
+        frame.bytes = &frame.bytes[..frame.bytes.len() - 1];
+        assert_eq!(frame.events().count(), 3);
+    }
+}
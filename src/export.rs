@@ -0,0 +1,406 @@
+//! Parquet export of replay events.
+//!
+//! Each command is written to its own table, `<command name>.parquet`, in an output directory.
+//! Every table has these columns:
+//!
+//! - `replay` (string): the name the replay was added with.
+//! - `event` (u32): the index of the event among the events after Game Start, to recover their order.
+//!
+//! Frame Start, Pre-Frame Update, Post-Frame Update, Item Update, Frame Bookend and Game End events
+//! have a column for each field of the Slippi replay spec.
+//! Fields added in later replay versions are null for older replays,
+//! and bytes after the known fields are in an `extra` (binary) column.
+//! Other commands have their payload in a `payload` (binary) column, and are named `command_<hex command>`.
+
+use crate::{events, Decompressor, DecompError};
+use crate::{PRE_FRAME_UPDATE, POST_FRAME_UPDATE, GAME_END, FRAME_START, ITEM_UPDATE, FRAME_BOOKEND};
+use arrow_array::{
+    ArrayRef, BinaryArray, Float32Array, Int32Array, Int8Array, RecordBatch, StringArray,
+    UInt16Array, UInt32Array, UInt8Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::arrow::ArrowWriter;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    Decompress(DecompError),
+    Parquet(parquet::errors::ParquetError),
+    Arrow(arrow_schema::ArrowError),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Io(e) => write!(f, "{}", e),
+            ExportError::Decompress(e) => write!(f, "{}", e),
+            ExportError::Parquet(e) => write!(f, "{}", e),
+            ExportError::Arrow(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(e: std::io::Error) -> Self { ExportError::Io(e) }
+}
+
+impl From<parquet::errors::ParquetError> for ExportError {
+    fn from(e: parquet::errors::ParquetError) -> Self { ExportError::Parquet(e) }
+}
+
+impl From<arrow_schema::ArrowError> for ExportError {
+    fn from(e: arrow_schema::ArrowError) -> Self { ExportError::Arrow(e) }
+}
+
+#[derive(Copy, Clone)]
+enum Type { U8, I8, U16, U32, I32, F32 }
+
+impl Type {
+    fn size(self) -> usize {
+        match self {
+            Type::U8 | Type::I8 => 1,
+            Type::U16 => 2,
+            Type::U32 | Type::I32 | Type::F32 => 4,
+        }
+    }
+}
+
+/// A field of an event. Offsets include the command byte, as in the Slippi replay spec.
+struct EventField {
+    name: &'static str,
+    offset: usize,
+    ty: Type,
+}
+
+const fn field(name: &'static str, offset: usize, ty: Type) -> EventField {
+    EventField { name, offset, ty }
+}
+
+const FRAME_START_FIELDS: &[EventField] = &[
+    field("frame", 0x1, Type::I32),
+    field("random_seed", 0x5, Type::U32),
+    field("scene_frame_counter", 0x9, Type::U32),
+];
+
+const PRE_FRAME_UPDATE_FIELDS: &[EventField] = &[
+    field("frame", 0x1, Type::I32),
+    field("player_index", 0x5, Type::U8),
+    field("is_follower", 0x6, Type::U8),
+    field("random_seed", 0x7, Type::U32),
+    field("action_state", 0xB, Type::U16),
+    field("x", 0xD, Type::F32),
+    field("y", 0x11, Type::F32),
+    field("facing", 0x15, Type::F32),
+    field("joystick_x", 0x19, Type::F32),
+    field("joystick_y", 0x1D, Type::F32),
+    field("cstick_x", 0x21, Type::F32),
+    field("cstick_y", 0x25, Type::F32),
+    field("trigger", 0x29, Type::F32),
+    field("buttons", 0x2D, Type::U32),
+    field("physical_buttons", 0x31, Type::U16),
+    field("physical_l", 0x33, Type::F32),
+    field("physical_r", 0x37, Type::F32),
+    field("ucf_x", 0x3B, Type::U8),
+    field("percent", 0x3C, Type::F32),
+    field("ucf_y", 0x40, Type::I8),
+];
+
+const POST_FRAME_UPDATE_FIELDS: &[EventField] = &[
+    field("frame", 0x1, Type::I32),
+    field("player_index", 0x5, Type::U8),
+    field("is_follower", 0x6, Type::U8),
+    field("character", 0x7, Type::U8),
+    field("action_state", 0x8, Type::U16),
+    field("x", 0xA, Type::F32),
+    field("y", 0xE, Type::F32),
+    field("facing", 0x12, Type::F32),
+    field("percent", 0x16, Type::F32),
+    field("shield_size", 0x1A, Type::F32),
+    field("last_attack_landed", 0x1E, Type::U8),
+    field("combo_count", 0x1F, Type::U8),
+    field("last_hit_by", 0x20, Type::U8),
+    field("stocks", 0x21, Type::U8),
+    field("action_state_frame", 0x22, Type::F32),
+    field("state_flags_1", 0x26, Type::U8),
+    field("state_flags_2", 0x27, Type::U8),
+    field("state_flags_3", 0x28, Type::U8),
+    field("state_flags_4", 0x29, Type::U8),
+    field("state_flags_5", 0x2A, Type::U8),
+    field("misc_as", 0x2B, Type::F32),
+    field("airborne", 0x2F, Type::U8),
+    field("last_ground", 0x30, Type::U16),
+    field("jumps_remaining", 0x32, Type::U8),
+    field("l_cancel", 0x33, Type::U8),
+    field("hurtbox_state", 0x34, Type::U8),
+    field("self_air_x_speed", 0x35, Type::F32),
+    field("self_y_speed", 0x39, Type::F32),
+    field("attack_x_speed", 0x3D, Type::F32),
+    field("attack_y_speed", 0x41, Type::F32),
+    field("self_ground_x_speed", 0x45, Type::F32),
+    field("hitlag_remaining", 0x49, Type::F32),
+    field("animation_index", 0x4D, Type::U32),
+];
+
+const ITEM_UPDATE_FIELDS: &[EventField] = &[
+    field("frame", 0x1, Type::I32),
+    field("type", 0x5, Type::U16),
+    field("state", 0x7, Type::U8),
+    field("facing", 0x8, Type::F32),
+    field("x_velocity", 0xC, Type::F32),
+    field("y_velocity", 0x10, Type::F32),
+    field("x", 0x14, Type::F32),
+    field("y", 0x18, Type::F32),
+    field("damage_taken", 0x1C, Type::U16),
+    field("expiration_timer", 0x1E, Type::F32),
+    field("spawn_id", 0x22, Type::U32),
+    field("misc_1", 0x26, Type::U8),
+    field("misc_2", 0x27, Type::U8),
+    field("misc_3", 0x28, Type::U8),
+    field("misc_4", 0x29, Type::U8),
+    field("owner", 0x2A, Type::I8),
+];
+
+const FRAME_BOOKEND_FIELDS: &[EventField] = &[
+    field("frame", 0x1, Type::I32),
+    field("latest_finalized_frame", 0x5, Type::I32),
+];
+
+const GAME_END_FIELDS: &[EventField] = &[
+    field("method", 0x1, Type::U8),
+    field("lras_initiator", 0x2, Type::I8),
+    field("placement_1", 0x3, Type::I8),
+    field("placement_2", 0x4, Type::I8),
+    field("placement_3", 0x5, Type::I8),
+    field("placement_4", 0x6, Type::I8),
+];
+
+/// The table name and fields of a command, or None for commands exported as raw payloads.
+fn known_command(command: u8) -> Option<(&'static str, &'static [EventField])> {
+    Some(match command {
+        FRAME_START => ("frame_start", FRAME_START_FIELDS),
+        PRE_FRAME_UPDATE => ("pre_frame_update", PRE_FRAME_UPDATE_FIELDS),
+        POST_FRAME_UPDATE => ("post_frame_update", POST_FRAME_UPDATE_FIELDS),
+        ITEM_UPDATE => ("item_update", ITEM_UPDATE_FIELDS),
+        FRAME_BOOKEND => ("frame_bookend", FRAME_BOOKEND_FIELDS),
+        GAME_END => ("game_end", GAME_END_FIELDS),
+        _ => return None,
+    })
+}
+
+fn schema(command: u8) -> SchemaRef {
+    let mut fields = vec![
+        Field::new("replay", DataType::Utf8, false),
+        Field::new("event", DataType::UInt32, false),
+    ];
+    match known_command(command) {
+        Some((_, event_fields)) => {
+            for f in event_fields {
+                let data_type = match f.ty {
+                    Type::U8 => DataType::UInt8,
+                    Type::I8 => DataType::Int8,
+                    Type::U16 => DataType::UInt16,
+                    Type::U32 => DataType::UInt32,
+                    Type::I32 => DataType::Int32,
+                    Type::F32 => DataType::Float32,
+                };
+                fields.push(Field::new(f.name, data_type, true));
+            }
+            fields.push(Field::new("extra", DataType::Binary, true));
+        }
+        None => fields.push(Field::new("payload", DataType::Binary, false)),
+    }
+    Arc::new(Schema::new(fields))
+}
+
+/// Events of a single command in a replay.
+#[derive(Default)]
+struct Rows {
+    events: Vec<u32>,
+    /// Payloads, each the payload size of the command.
+    payloads: Vec<u8>,
+}
+
+/// Writes the events of many replays to Parquet tables in a directory. See the module docs.
+pub struct ParquetExporter {
+    dir: std::path::PathBuf,
+    writers: HashMap<u8, (ArrowWriter<std::fs::File>, SchemaRef)>,
+}
+
+impl ParquetExporter {
+    /// Creates the directory if needed. Tables already in it are replaced.
+    pub fn create(dir: &std::path::Path) -> Result<ParquetExporter, ExportError> {
+        std::fs::create_dir_all(dir)?;
+        Ok(ParquetExporter { dir: dir.to_path_buf(), writers: HashMap::new() })
+    }
+
+    /// Adds the events of an slp or slpz file.
+    pub fn add(&mut self, decompressor: &mut Decompressor, replay: &str, file: &[u8]) -> Result<(), ExportError> {
+        let mut events = events(decompressor, file).map_err(ExportError::Decompress)?;
+        let event_sizes = *events.event_sizes();
+
+        let mut rows: HashMap<u8, Rows> = HashMap::new();
+        let mut i = 0u32;
+        while let Some((command, payload)) = events.next_event() {
+            let r = rows.entry(command).or_default();
+            r.events.push(i);
+            r.payloads.extend_from_slice(payload);
+            i += 1;
+        }
+
+        let mut commands = rows.keys().copied().collect::<Vec<_>>();
+        commands.sort_unstable();
+        for command in commands {
+            let r = &rows[&command];
+            let size = event_sizes[command as usize] as usize;
+            let payloads = || r.payloads.chunks_exact(size);
+
+            let (writer, schema) = match self.writers.entry(command) {
+                std::collections::hash_map::Entry::Occupied(e) => e.into_mut(),
+                std::collections::hash_map::Entry::Vacant(e) => {
+                    let name = match known_command(command) {
+                        Some((name, _)) => name.to_string(),
+                        None => format!("command_{:02x}", command),
+                    };
+                    let file = std::fs::File::create(self.dir.join(format!("{}.parquet", name)))?;
+                    let schema = schema(command);
+                    let props = parquet::file::properties::WriterProperties::builder()
+                        .set_compression(parquet::basic::Compression::SNAPPY)
+                        .build();
+                    let writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
+                    e.insert((writer, schema))
+                }
+            };
+
+            let mut columns: Vec<ArrayRef> = vec![
+                Arc::new(StringArray::from(vec![replay; r.events.len()])),
+                Arc::new(UInt32Array::from(r.events.clone())),
+            ];
+            match known_command(command) {
+                Some((_, fields)) => {
+                    let mut known_size = 0;
+                    for f in fields {
+                        // without the command byte
+                        let start = f.offset - 1;
+                        let end = start + f.ty.size();
+                        macro_rules! column {
+                            ($array:ident, $ty:ty) => {
+                                Arc::new($array::from(payloads()
+                                    .map(|p| p.get(start..end).map(|b| <$ty>::from_be_bytes(b.try_into().unwrap())))
+                                    .collect::<Vec<_>>()))
+                            }
+                        }
+                        let column: ArrayRef = match f.ty {
+                            Type::U8 => column!(UInt8Array, u8),
+                            Type::I8 => column!(Int8Array, i8),
+                            Type::U16 => column!(UInt16Array, u16),
+                            Type::U32 => column!(UInt32Array, u32),
+                            Type::I32 => column!(Int32Array, i32),
+                            Type::F32 => column!(Float32Array, f32),
+                        };
+                        columns.push(column);
+                        if end <= size { known_size = end }
+                    }
+                    let extra = payloads().map(|p| p.get(known_size..).filter(|e| !e.is_empty())).collect::<Vec<_>>();
+                    columns.push(Arc::new(BinaryArray::from(extra)));
+                }
+                None => columns.push(Arc::new(BinaryArray::from(payloads().collect::<Vec<_>>()))),
+            }
+
+            writer.write(&RecordBatch::try_new(schema.clone(), columns)?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the footers of every table. Tables are unreadable until this is called.
+    pub fn finish(self) -> Result<(), ExportError> {
+        for (_, (writer, _)) in self.writers {
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay;
+    use crate::{compress, Compressor};
+    use arrow_array::Array;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    fn read_table(dir: &std::path::Path, name: &str) -> Vec<RecordBatch> {
+        let file = std::fs::File::open(dir.join(format!("{}.parquet", name))).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+        reader.collect::<Result<Vec<_>, _>>().unwrap()
+    }
+
+    /// The values of a column in every batch, None for nulls.
+    fn column<A: Array + 'static, T>(
+        batches: &[RecordBatch],
+        name: &str,
+        value: impl Fn(&A, usize) -> T,
+    ) -> Vec<Option<T>> {
+        let mut values = Vec::new();
+        for batch in batches {
+            let array = batch.column_by_name(name).unwrap().as_any().downcast_ref::<A>().unwrap();
+            values.extend((0..array.len()).map(|i| array.is_valid(i).then(|| value(array, i))));
+        }
+        values
+    }
+
+    #[test]
+    fn export() {
+        let dir = std::env::temp_dir().join(format!("slpz-export-test-{}", std::process::id()));
+        let slp = replay(-123, 50);
+        let slpz = compress(&mut Compressor::new(3).unwrap(), &replay(0, 9)).unwrap();
+
+        let mut decompressor = Decompressor::new().unwrap();
+        let mut exporter = ParquetExporter::create(&dir).unwrap();
+        exporter.add(&mut decompressor, "a.slp", &slp).unwrap();
+        exporter.add(&mut decompressor, "b.slpz", &slpz).unwrap();
+        exporter.finish().unwrap();
+
+        let mut tables = std::fs::read_dir(&dir).unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        tables.sort();
+        assert_eq!(tables, [
+            "frame_bookend.parquet", "frame_start.parquet", "game_end.parquet",
+            "post_frame_update.parquet", "pre_frame_update.parquet",
+        ]);
+
+        // the fixture's frames are a Frame Start, Pre-Frame, Post-Frame and Frame Bookend
+        let frames = (-123..=50).chain(0..=9).collect::<Vec<i32>>();
+        let replays = [("a.slp", 174), ("b.slpz", 10)].iter()
+            .flat_map(|&(name, n)| std::iter::repeat_n(Some(name.to_string()), n))
+            .collect::<Vec<_>>();
+        let events = (0..174).chain(0..10).map(|i| Some(i * 4 + 1)).collect::<Vec<Option<u32>>>();
+
+        let pre = read_table(&dir, "pre_frame_update");
+        assert_eq!(column(&pre, "replay", |a: &StringArray, i| a.value(i).to_string()), replays);
+        assert_eq!(column(&pre, "event", |a: &UInt32Array, i| a.value(i)), events);
+        assert_eq!(column(&pre, "frame", |a: &Int32Array, i| a.value(i)), frames.iter().map(|&f| Some(f)).collect::<Vec<_>>());
+        // payload bytes 4.. are `frame ^ byte index`, without the command byte
+        let seeds = frames.iter()
+            .map(|&f| Some(u32::from_be_bytes([6, 7, 8, 9].map(|i| (f as u16 ^ i) as u8))))
+            .collect::<Vec<_>>();
+        assert_eq!(column(&pre, "random_seed", |a: &UInt32Array, i| a.value(i)), seeds);
+        // ucf_y is past the fixture's payload size
+        assert!(column(&pre, "ucf_y", |a: &Int8Array, i| a.value(i)).iter().all(Option::is_none));
+        assert!(column(&pre, "extra", |a: &BinaryArray, i| a.value(i).to_vec()).iter().all(Option::is_none));
+
+        let post = read_table(&dir, "post_frame_update");
+        assert_eq!(column(&post, "frame", |a: &Int32Array, i| a.value(i)).len(), frames.len());
+
+        let game_end = read_table(&dir, "game_end");
+        assert_eq!(column(&game_end, "event", |a: &UInt32Array, i| a.value(i)), [Some(174 * 4), Some(10 * 4)]);
+        assert_eq!(column(&game_end, "method", |a: &UInt8Array, i| a.value(i)), [Some(2), Some(2)]);
+        assert_eq!(column(&game_end, "lras_initiator", |a: &Int8Array, i| a.value(i)), [Some(0), Some(0)]);
+        assert_eq!(column(&game_end, "placement_1", |a: &Int8Array, i| a.value(i)), [None, None]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
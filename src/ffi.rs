@@ -0,0 +1,253 @@
+//! C ABI for embedding slpz in non-Rust programs.
+//!
+//! Build with `cargo rustc --lib --release --features ffi --crate-type staticlib` (or `cdylib`).
+//!
+//! The C header is at `include/slpz.h` and is generated by cbindgen:
+//! `cbindgen --config cbindgen.toml --output include/slpz.h src/ffi.rs`
+//!
+//! All functions returning `int32_t` return one of the `SLPZ_` error codes.
+
+use crate::{Compressor, Decompressor, CompError, DecompError, Header};
+
+pub const SLPZ_OK: i32 = 0;
+/// Mirrors `CompError::InvalidFile` and `DecompError::InvalidFile`.
+pub const SLPZ_ERR_INVALID_FILE: i32 = 1;
+/// Mirrors `CompError::CompressionFailure`.
+pub const SLPZ_ERR_COMPRESSION_FAILURE: i32 = 2;
+/// Mirrors `DecompError::DecompressionFailure`.
+pub const SLPZ_ERR_DECOMPRESSION_FAILURE: i32 = 3;
+/// The output buffer is too small. The required size is written to `out_len`.
+pub const SLPZ_ERR_BUFFER_TOO_SMALL: i32 = 4;
+/// A required pointer was null.
+pub const SLPZ_ERR_NULL_POINTER: i32 = 5;
+
+/// Opaque compression context.
+pub struct SlpzCompressor {
+    compressor: Compressor,
+    slpz: Vec<u8>,
+}
+
+/// Opaque decompression context.
+pub struct SlpzDecompressor {
+    decompressor: Decompressor,
+    slp: Vec<u8>,
+}
+
+/// The header at the start of every slpz file. Offsets are from file start.
+#[repr(C)]
+pub struct SlpzHeader {
+    pub version: u32,
+    pub event_sizes_offset: usize,
+    pub game_start_offset: usize,
+    pub metadata_offset: usize,
+    pub compressed_events_offset: usize,
+    pub decompressed_events_size: usize,
+    /// The exact size of the slp file this slpz file decompresses to.
+    pub decompressed_size: usize,
+}
+
+fn comp_error_code(e: CompError) -> i32 {
+    match e {
+        CompError::InvalidFile => SLPZ_ERR_INVALID_FILE,
+        CompError::CompressionFailure => SLPZ_ERR_COMPRESSION_FAILURE,
+    }
+}
+
+fn decomp_error_code(e: DecompError) -> i32 {
+    match e {
+        DecompError::InvalidFile => SLPZ_ERR_INVALID_FILE,
+        DecompError::DecompressionFailure => SLPZ_ERR_DECOMPRESSION_FAILURE,
+    }
+}
+
+/// Returns None if `ptr` is null.
+unsafe fn input<'a>(ptr: *const u8, len: usize) -> Option<&'a [u8]> {
+    if ptr.is_null() { return None }
+    Some(std::slice::from_raw_parts(ptr, len))
+}
+
+/// Creates a compressor. compression_level should be between 1..=19. The default is 3.
+///
+/// Returns null on failure. Must be freed with `slpz_compressor_free`.
+#[no_mangle]
+pub extern "C" fn slpz_compressor_new(compression_level: i32) -> *mut SlpzCompressor {
+    match Compressor::new(compression_level) {
+        Some(compressor) => Box::into_raw(Box::new(SlpzCompressor { compressor, slpz: Vec::new() })),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Frees a compressor. Passing null does nothing.
+///
+/// # Safety
+/// `compressor` must be null or a pointer returned by `slpz_compressor_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn slpz_compressor_free(compressor: *mut SlpzCompressor) {
+    if !compressor.is_null() { drop(Box::from_raw(compressor)) }
+}
+
+/// Creates a decompressor.
+///
+/// Returns null on failure. Must be freed with `slpz_decompressor_free`.
+#[no_mangle]
+pub extern "C" fn slpz_decompressor_new() -> *mut SlpzDecompressor {
+    match Decompressor::new() {
+        Some(decompressor) => Box::into_raw(Box::new(SlpzDecompressor { decompressor, slp: Vec::new() })),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Frees a decompressor. Passing null does nothing.
+///
+/// # Safety
+/// `decompressor` must be null or a pointer returned by `slpz_decompressor_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn slpz_decompressor_free(decompressor: *mut SlpzDecompressor) {
+    if !decompressor.is_null() { drop(Box::from_raw(decompressor)) }
+}
+
+/// Returns an upper bound on the size of the slpz file an slp file of `slp_len` bytes compresses to.
+#[no_mangle]
+pub extern "C" fn slpz_compress_bound(slp_len: usize) -> usize {
+    crate::compress_bound(slp_len)
+}
+
+/// Compresses an slp file into `out`.
+///
+/// The size of the slpz file is written to `out_len`.
+/// If `out_cap` is too small, nothing is written to `out`, `SLPZ_ERR_BUFFER_TOO_SMALL` is returned,
+/// and the required size is written to `out_len`.
+/// Use `slpz_compress_bound` to size `out` beforehand.
+///
+/// # Safety
+/// `slp` must point to `slp_len` readable bytes and `out` must point to `out_cap` writable bytes.
+/// `compressor` must be a valid compressor.
+#[no_mangle]
+pub unsafe extern "C" fn slpz_compress(
+    compressor: *mut SlpzCompressor,
+    slp: *const u8,
+    slp_len: usize,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> i32 {
+    if compressor.is_null() || out.is_null() || out_len.is_null() { return SLPZ_ERR_NULL_POINTER }
+    let compressor = &mut *compressor;
+    let slp = match input(slp, slp_len) {
+        Some(slp) => slp,
+        None => return SLPZ_ERR_NULL_POINTER,
+    };
+
+    if let Err(e) = compressor.compressor.compress_into(slp, &mut compressor.slpz) {
+        return comp_error_code(e);
+    }
+
+    let slpz = &compressor.slpz;
+    *out_len = slpz.len();
+    if slpz.len() > out_cap { return SLPZ_ERR_BUFFER_TOO_SMALL }
+    std::ptr::copy_nonoverlapping(slpz.as_ptr(), out, slpz.len());
+    SLPZ_OK
+}
+
+/// Decompresses an slpz file into `out`.
+///
+/// The size of the slp file is written to `out_len`.
+/// If `out_cap` is too small, nothing is written to `out`, `SLPZ_ERR_BUFFER_TOO_SMALL` is returned,
+/// and the required size is written to `out_len`.
+/// Use `slpz_decompressed_size` to size `out` beforehand.
+///
+/// # Safety
+/// `slpz` must point to `slpz_len` readable bytes and `out` must point to `out_cap` writable bytes.
+/// `decompressor` must be a valid decompressor.
+#[no_mangle]
+pub unsafe extern "C" fn slpz_decompress(
+    decompressor: *mut SlpzDecompressor,
+    slpz: *const u8,
+    slpz_len: usize,
+    out: *mut u8,
+    out_cap: usize,
+    out_len: *mut usize,
+) -> i32 {
+    if decompressor.is_null() || out.is_null() || out_len.is_null() { return SLPZ_ERR_NULL_POINTER }
+    let decompressor = &mut *decompressor;
+    let slpz = match input(slpz, slpz_len) {
+        Some(slpz) => slpz,
+        None => return SLPZ_ERR_NULL_POINTER,
+    };
+
+    let size = match crate::decompressed_size(slpz) {
+        Ok(size) => size,
+        Err(e) => return decomp_error_code(e),
+    };
+    *out_len = size;
+    if size > out_cap { return SLPZ_ERR_BUFFER_TOO_SMALL }
+
+    if let Err(e) = decompressor.decompressor.decompress_into(slpz, &mut decompressor.slp) {
+        return decomp_error_code(e);
+    }
+
+    // the header may not match the events in invalid files
+    let slp = &decompressor.slp;
+    if slp.len() > out_cap { return SLPZ_ERR_INVALID_FILE }
+    *out_len = slp.len();
+    std::ptr::copy_nonoverlapping(slp.as_ptr(), out, slp.len());
+    SLPZ_OK
+}
+
+/// Writes the exact size of the slp file an slpz file decompresses to into `out_size`.
+///
+/// # Safety
+/// `slpz` must point to `slpz_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn slpz_decompressed_size(
+    slpz: *const u8,
+    slpz_len: usize,
+    out_size: *mut usize,
+) -> i32 {
+    if out_size.is_null() { return SLPZ_ERR_NULL_POINTER }
+    let slpz = match input(slpz, slpz_len) {
+        Some(slpz) => slpz,
+        None => return SLPZ_ERR_NULL_POINTER,
+    };
+
+    match crate::decompressed_size(slpz) {
+        Ok(size) => {
+            *out_size = size;
+            SLPZ_OK
+        }
+        Err(e) => decomp_error_code(e),
+    }
+}
+
+/// Reads and validates the header of an slpz file into `out_header`.
+///
+/// # Safety
+/// `slpz` must point to `slpz_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn slpz_read_header(
+    slpz: *const u8,
+    slpz_len: usize,
+    out_header: *mut SlpzHeader,
+) -> i32 {
+    if out_header.is_null() { return SLPZ_ERR_NULL_POINTER }
+    let slpz = match input(slpz, slpz_len) {
+        Some(slpz) => slpz,
+        None => return SLPZ_ERR_NULL_POINTER,
+    };
+
+    match Header::read(slpz) {
+        Ok(header) => {
+            *out_header = SlpzHeader {
+                version: header.version,
+                event_sizes_offset: header.event_sizes_offset,
+                game_start_offset: header.game_start_offset,
+                metadata_offset: header.metadata_offset,
+                compressed_events_offset: header.compressed_events_offset,
+                decompressed_events_size: header.decompressed_events_size,
+                decompressed_size: header.decompressed_size(),
+            };
+            SLPZ_OK
+        }
+        Err(e) => decomp_error_code(e),
+    }
+}
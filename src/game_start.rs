@@ -0,0 +1,176 @@
+//! Fields of the Game Start event.
+//!
+//! Offsets include the command byte, as in the Slippi replay spec.
+//! Fields added in later replay versions return None for older replays.
+
+const DISPLAY_NAMES_OFFSET: usize = 0x1A5;
+const DISPLAY_NAME_SIZE: usize = 0x1F;
+
+/// The in-game display name of a port (0 to 3), Shift JIS encoded. Empty if not set.
+pub fn display_name(game_start: &[u8], port: usize) -> Option<&[u8]> {
+    if port > 3 { return None }
+    let offset = DISPLAY_NAMES_OFFSET + DISPLAY_NAME_SIZE * port;
+    let name = game_start.get(offset..offset + DISPLAY_NAME_SIZE)?;
+    Some(&name[..name.iter().position(|&b| b == 0).unwrap_or(name.len())])
+}
+
+/// Sets the display name of a port (0 to 3).
+///
+/// Only ASCII names that fit, with their nul terminator, are set. Returns whether the name was set.
+pub fn set_display_name(game_start: &mut [u8], port: usize, name: &str) -> bool {
+    if port > 3 || !name.is_ascii() || name.len() >= DISPLAY_NAME_SIZE { return false }
+    let offset = DISPLAY_NAMES_OFFSET + DISPLAY_NAME_SIZE * port;
+    let Some(field) = game_start.get_mut(offset..offset + DISPLAY_NAME_SIZE) else { return false };
+    field.fill(0);
+    field[..name.len()].copy_from_slice(name.as_bytes());
+    true
+}
+
+const NAMETAGS_OFFSET: usize = 0x161;
+const NAMETAG_SIZE: usize = 0x10;
+const CONNECT_CODES_OFFSET: usize = 0x221;
+const CONNECT_CODE_SIZE: usize = 0xA;
+const USER_IDS_OFFSET: usize = 0x249;
+const USER_ID_SIZE: usize = 0x1D;
+
+/// The Shift JIS '＃' in connect codes.
+const FULLWIDTH_HASH: [u8; 2] = [0x81, 0x94];
+
+/// The netplay connect code of a port (0 to 3), such as `ABCD#123`. Empty if not set.
+pub fn connect_code(game_start: &[u8], port: usize) -> Option<alloc::string::String> {
+    if port > 3 { return None }
+    let offset = CONNECT_CODES_OFFSET + CONNECT_CODE_SIZE * port;
+    let code = game_start.get(offset..offset + CONNECT_CODE_SIZE)?;
+    let code = &code[..code.iter().position(|&b| b == 0).unwrap_or(code.len())];
+
+    let mut s = alloc::string::String::new();
+    let mut i = 0;
+    while i < code.len() {
+        if code[i..].starts_with(&FULLWIDTH_HASH) {
+            s.push('#');
+            i += 2;
+        } else {
+            s.push(code[i] as char);
+            i += 1;
+        }
+    }
+    Some(s)
+}
+
+/// Sets the connect code of a port (0 to 3).
+///
+/// Only ASCII codes that fit are set. Returns whether the code was set.
+pub fn set_connect_code(game_start: &mut [u8], port: usize, code: &str) -> bool {
+    if port > 3 || !code.is_ascii() { return false }
+    let offset = CONNECT_CODES_OFFSET + CONNECT_CODE_SIZE * port;
+    let Some(field) = game_start.get_mut(offset..offset + CONNECT_CODE_SIZE) else { return false };
+
+    let mut encoded = alloc::vec::Vec::new();
+    for b in code.bytes() {
+        match b {
+            b'#' => encoded.extend_from_slice(&FULLWIDTH_HASH),
+            b => encoded.push(b),
+        }
+    }
+    // keep the nul terminator
+    if encoded.len() >= CONNECT_CODE_SIZE { return false }
+
+    field.fill(0);
+    field[..encoded.len()].copy_from_slice(&encoded);
+    true
+}
+
+/// Clears the in-game nametag, display name, connect code and Slippi user ID of a port (0 to 3).
+pub fn clear_player_names(game_start: &mut [u8], port: usize) {
+    if port > 3 { return }
+    let fields = [
+        (NAMETAGS_OFFSET, NAMETAG_SIZE),
+        (DISPLAY_NAMES_OFFSET, DISPLAY_NAME_SIZE),
+        (CONNECT_CODES_OFFSET, CONNECT_CODE_SIZE),
+        (USER_IDS_OFFSET, USER_ID_SIZE),
+    ];
+    for (offset, size) in fields {
+        let offset = offset + size * port;
+        if let Some(field) = game_start.get_mut(offset..offset + size) { field.fill(0) }
+    }
+}
+
+const STAGE_OFFSET: usize = 0x13;
+const CHARACTERS_OFFSET: usize = 0x65;
+const PLAYER_TYPES_OFFSET: usize = 0x66;
+const PLAYER_BLOCK_SIZE: usize = 0x24;
+
+/// Player type of an empty port.
+pub const PLAYER_TYPE_EMPTY: u8 = 3;
+
+/// The external stage ID.
+pub fn stage(game_start: &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes(game_start.get(STAGE_OFFSET..STAGE_OFFSET + 2)?.try_into().unwrap()))
+}
+
+/// The external character ID of a port (0 to 3).
+pub fn character(game_start: &[u8], port: usize) -> Option<u8> {
+    if port > 3 { return None }
+    game_start.get(CHARACTERS_OFFSET + PLAYER_BLOCK_SIZE * port).copied()
+}
+
+/// The player type of a port (0 to 3): 0 for human, 1 for CPU, 2 for demo and 3 for empty.
+pub fn player_type(game_start: &[u8], port: usize) -> Option<u8> {
+    if port > 3 { return None }
+    game_start.get(PLAYER_TYPES_OFFSET + PLAYER_BLOCK_SIZE * port).copied()
+}
+
+/// The name of an external stage ID.
+pub fn stage_name(stage: u16) -> Option<&'static str> {
+    const STAGES: [&str; 33] = [
+        "Dummy", "Test", "Fountain of Dreams", "Pokemon Stadium", "Princess Peach's Castle",
+        "Kongo Jungle", "Brinstar", "Corneria", "Yoshi's Story", "Onett", "Mute City",
+        "Rainbow Cruise", "Jungle Japes", "Great Bay", "Hyrule Temple", "Brinstar Depths",
+        "Yoshi's Island", "Green Greens", "Fourside", "Mushroom Kingdom I", "Mushroom Kingdom II",
+        "Akaneia", "Venom", "Poke Floats", "Big Blue", "Icicle Mountain", "Icetop", "Flat Zone",
+        "Dream Land N64", "Yoshi's Island N64", "Kongo Jungle N64", "Battlefield", "Final Destination",
+    ];
+    STAGES.get(stage as usize).copied()
+}
+
+/// The name of an external character ID.
+pub fn character_name(character: u8) -> Option<&'static str> {
+    const CHARACTERS: [&str; 26] = [
+        "Captain Falcon", "Donkey Kong", "Fox", "Mr. Game & Watch", "Kirby", "Bowser", "Link",
+        "Luigi", "Mario", "Marth", "Mewtwo", "Ness", "Peach", "Pikachu", "Ice Climbers",
+        "Jigglypuff", "Samus", "Yoshi", "Zelda", "Sheik", "Falco", "Young Link", "Dr. Mario",
+        "Roy", "Pichu", "Ganondorf",
+    ];
+    CHARACTERS.get(character as usize).copied()
+}
+
+const RANDOM_SEED_OFFSET: usize = 0x13D;
+const MATCH_ID_OFFSET: usize = 0x2BE;
+const MATCH_ID_SIZE: usize = 0x33;
+const GAME_NUMBER_OFFSET: usize = 0x2F1;
+const TIEBREAKER_OFFSET: usize = 0x2F5;
+
+/// The random seed the game started with.
+pub fn random_seed(game_start: &[u8]) -> Option<u32> {
+    u32_at(game_start, RANDOM_SEED_OFFSET)
+}
+
+/// The online match ID, such as `mode.ranked-2024-01-01T12:00:00.00-0`. Empty for offline games.
+pub fn match_id(game_start: &[u8]) -> Option<&[u8]> {
+    let id = game_start.get(MATCH_ID_OFFSET..MATCH_ID_OFFSET + MATCH_ID_SIZE)?;
+    Some(&id[..id.iter().position(|&b| b == 0).unwrap_or(id.len())])
+}
+
+/// The game number within the match, starting at 1.
+pub fn game_number(game_start: &[u8]) -> Option<u32> {
+    u32_at(game_start, GAME_NUMBER_OFFSET)
+}
+
+/// The tiebreaker number of the game, 0 unless the game was a tiebreaker.
+pub fn tiebreaker(game_start: &[u8]) -> Option<u32> {
+    u32_at(game_start, TIEBREAKER_OFFSET)
+}
+
+fn u32_at(game_start: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(game_start.get(offset..offset + 4)?.try_into().unwrap()))
+}
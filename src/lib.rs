@@ -9,6 +9,46 @@
 //! The slpz format is documented in the readme in the repo.
 //! Important information, such as player tags, stages, date, characters, etc. all remain uncompressed in the slpz format. 
 //! This allows slp file browsers to easily parse and display this information without needing to decompress the replay.
+//!
+//! Without the default `std` feature, only the codec core is built, 
+//! which requires `alloc` and a C compiler for zstd.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+pub mod ubjson;
+pub mod game_start;
+mod anonymize;
+pub use anonymize::*;
+mod trim;
+pub use trim::*;
+mod events;
+pub use events::*;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "async")]
+pub mod tokio;
+
+// filesystem and threading are not available in the browser
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+mod target;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub use target::*;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+mod archive;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub use archive::*;
+#[cfg(feature = "parquet")]
+mod export;
+#[cfg(feature = "parquet")]
+pub use export::*;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum CompError {
@@ -22,16 +62,8 @@ pub enum DecompError {
     DecompressionFailure,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-pub enum TargetPathError {
-    PathNotFound,
-    PathInvalid,
-    CompressOrDecompressAmbiguous,
-    ZstdInitError,
-}
-
-impl std::fmt::Display for CompError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for CompError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", match self {
             CompError::InvalidFile => "File is invalid",
             CompError::CompressionFailure => "Compression failed",
@@ -39,8 +71,8 @@ impl std::fmt::Display for CompError {
     }
 }
 
-impl std::fmt::Display for DecompError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for DecompError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", match self {
             DecompError::InvalidFile => "File is invalid",
             DecompError::DecompressionFailure => "Decompression failed",
@@ -48,137 +80,467 @@ impl std::fmt::Display for DecompError {
     }
 }
 
-impl std::fmt::Display for TargetPathError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EditError {
+    InvalidFile,
+    /// Game Start must stay the size given in the Event Payloads event.
+    GameStartSize,
+    /// The metadata section is too large for the slpz header.
+    MetadataSize,
+    /// The metadata is not the UBJSON Slippi writes.
+    InvalidMetadata,
+}
+
+impl core::fmt::Display for EditError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", match self {
+            EditError::InvalidFile => "File is invalid",
+            EditError::GameStartSize => "Game Start size cannot change",
+            EditError::MetadataSize => "Metadata is too large",
+            EditError::InvalidMetadata => "Metadata is invalid",
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TrimError {
+    InvalidFile,
+    /// The metadata is not the UBJSON Slippi writes.
+    InvalidMetadata,
+    /// No frames of the replay are in the range.
+    EmptyRange,
+}
+
+impl core::fmt::Display for TrimError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", match self {
-            TargetPathError::PathNotFound => "Replay path not found",
-            TargetPathError::PathInvalid => "Replay path invalid",
-            TargetPathError::CompressOrDecompressAmbiguous => "Not a slp or slpz file",
-            TargetPathError::ZstdInitError => "Failed to init zstd",
+            TrimError::InvalidFile => "File is invalid",
+            TrimError::InvalidMetadata => "Metadata is invalid",
+            TrimError::EmptyRange => "No frames in range",
         })
     }
 }
 
 const EVENT_PAYLOADS: u8 = 0x35;
 const GAME_START: u8 = 0x36;
+const PRE_FRAME_UPDATE: u8 = 0x37;
+const POST_FRAME_UPDATE: u8 = 0x38;
+const GAME_END: u8 = 0x39;
+const FRAME_START: u8 = 0x3A;
+const ITEM_UPDATE: u8 = 0x3B;
+const FRAME_BOOKEND: u8 = 0x3C;
 const RAW_HEADER: [u8; 11] = [0x7B, 0x55, 0x03, 0x72, 0x61, 0x77, 0x5B, 0x24, 0x55, 0x23, 0x6C];
 
+const HEADER_SIZE: usize = 24;
+
 pub const VERSION: u32 = 0;
 
-pub struct Compressor { ctx: zstd::bulk::Compressor<'static> }
-pub struct Decompressor { ctx: zstd::bulk::Decompressor<'static> }
+/// Compression context.
+/// 
+/// Holds scratch space that is reused between calls to `compress_into`.
+pub struct Compressor { 
+    ctx: zstd_safe::CCtx<'static>, 
+    reordered: Vec<u8>,
+    #[cfg(feature = "zstdmt")]
+    workers: u32,
+}
+
+/// Decompression context.
+/// 
+/// Holds scratch space that is reused between calls to `decompress_into`.
+pub struct Decompressor { 
+    ctx: zstd_safe::DCtx<'static>,
+    reordered: Vec<u8>,
+}
+
+/// zstd will not split work into jobs smaller than this.
+#[cfg(feature = "zstdmt")]
+const MIN_JOB_SIZE: usize = 512 * 1024;
 
 impl Compressor {
     /// compression_level should be between 1..=19. The default is 3.
     pub fn new(compression_level: i32) -> Option<Compressor> {
+        let mut ctx = zstd_safe::CCtx::try_create()?;
+        ctx.set_parameter(zstd_safe::CParameter::CompressionLevel(compression_level)).ok()?;
         Some(Compressor {
-            ctx: zstd::bulk::Compressor::new(compression_level).ok()?
+            ctx,
+            reordered: Vec::new(),
+            #[cfg(feature = "zstdmt")]
+            workers: 0,
         })
     }
+
+    /// Compresses each replay using `workers` zstd worker threads.
+    ///
+    /// This speeds up compressing a single large replay. 
+    /// Replays too small to be split between workers are compressed as usual.
+    /// Passing 0 workers is equivalent to `Compressor::new`.
+    #[cfg(feature = "zstdmt")]
+    pub fn with_workers(compression_level: i32, workers: u32) -> Option<Compressor> {
+        let mut compressor = Compressor::new(compression_level)?;
+        compressor.ctx.set_parameter(zstd_safe::CParameter::NbWorkers(workers)).ok()?;
+        compressor.workers = workers;
+        Some(compressor)
+    }
+
+    /// Compresses each replay using a zstd dictionary, such as one from `train_dictionary`.
+    ///
+    /// The slpz files can only be decompressed by a `Decompressor` with the same dictionary.
+    pub fn with_dictionary(compression_level: i32, dictionary: &[u8]) -> Option<Compressor> {
+        let mut compressor = Compressor::new(compression_level)?;
+        compressor.ctx.load_dictionary(dictionary).ok()?;
+        Some(compressor)
+    }
+
+    /// Compresses an slp file to an slpz file, replacing the contents of `slpz`.
+    ///
+    /// Does not allocate if `slpz` and the compressor's scratch space are large enough,
+    /// so reusing both for many files avoids allocating for every file.
+    pub fn compress_into(&mut self, slp: &[u8], slpz: &mut Vec<u8>) -> Result<(), CompError> {
+        let len = self.write_sections(slp, slpz)?;
+
+        // split events evenly between workers
+        #[cfg(feature = "zstdmt")]
+        if self.workers > 1 {
+            let job_size = (self.reordered.len() / self.workers as usize).max(MIN_JOB_SIZE);
+            self.ctx.set_parameter(zstd_safe::CParameter::JobSize(job_size as u32))
+                .map_err(|_| CompError::CompressionFailure)?;
+        }
+
+        // compress after the previous data
+        slpz.resize(len + zstd_safe::compress_bound(self.reordered.len()), 0u8);
+        let written = self.ctx.compress2(&mut slpz[len..], &self.reordered).map_err(|_| CompError::CompressionFailure)?;
+        slpz.truncate(len + written);
+
+        Ok(())
+    }
+
+    /// Writes everything but the compressed events to `slpz`, and the reordered events to `self.reordered`.
+    ///
+    /// Returns the offset of the compressed events.
+    fn write_sections(&mut self, slp: &[u8], slpz: &mut Vec<u8>) -> Result<usize, CompError> {
+        let SlpLayout { event_sizes, event_sizes_size, game_start_offset, game_start_size, metadata_offset } 
+            = SlpLayout::read(slp)?;
+        let event_sizes_payload = &slp[15..][..event_sizes_size];
+        let game_start_payload = &slp[game_start_offset..][..game_start_size];
+        let metadata = &slp[metadata_offset..];
+
+        slpz.clear();
+
+        // header
+        slpz.extend_from_slice(&VERSION.to_be_bytes());
+        slpz.extend_from_slice(&[0u8; 20]); // offsets filled later
+
+        // write event sizes
+        let len = slpz.len() as u32;
+        slpz[4..8].copy_from_slice(&len.to_be_bytes());
+        slpz.extend_from_slice(event_sizes_payload);
+
+        // write game start
+        let len = slpz.len() as u32;
+        slpz[8..12].copy_from_slice(&len.to_be_bytes());
+        slpz.extend_from_slice(game_start_payload);
+
+        // write metadata
+        let len = slpz.len() as u32;
+        slpz[12..16].copy_from_slice(&len.to_be_bytes());
+        slpz.extend_from_slice(metadata);
+
+        // write compressed events
+        let len = slpz.len() as u32;
+        slpz[16..20].copy_from_slice(&len.to_be_bytes());
+
+        let other_events_offset = game_start_offset+game_start_size;
+        self.reordered.clear();
+        let written = reorder_events(&slp[other_events_offset..metadata_offset], &event_sizes, &mut self.reordered)?;
+        slpz[20..24].copy_from_slice(&(written as u32).to_be_bytes());
+
+        Ok(len as usize)
+    }
 }
 
 impl Decompressor {
     pub fn new() -> Option<Decompressor> {
-        Some(Decompressor { ctx: zstd::bulk::Decompressor::new().ok()? })
+        Some(Decompressor { 
+            ctx: zstd_safe::DCtx::try_create()?,
+            reordered: Vec::new(),
+        })
+    }
+
+    /// Decompresses slpz files compressed with `Compressor::with_dictionary`.
+    pub fn with_dictionary(dictionary: &[u8]) -> Option<Decompressor> {
+        let mut decompressor = Decompressor::new()?;
+        decompressor.ctx.load_dictionary(dictionary).ok()?;
+        Some(decompressor)
+    }
+
+    /// Decompresses an slpz file to an slp file, replacing the contents of `slp`.
+    ///
+    /// Does not allocate if `slp` and the decompressor's scratch space are large enough,
+    /// so reusing both for many files avoids allocating for every file.
+    pub fn decompress_into(&mut self, slpz: &[u8], slp: &mut Vec<u8>) -> Result<(), DecompError> {
+        let header = Header::read(slpz)?;
+        let Header { event_sizes_offset, game_start_offset, metadata_offset, compressed_events_offset, .. } = header;
+
+        slp.clear();
+        slp.reserve(header.decompressed_size());
+        slp.extend_from_slice(&RAW_HEADER);
+        slp.extend_from_slice(&[0u8; 4]); // raw len. filled in later
+
+        let event_sizes_bytes = &slpz[event_sizes_offset..game_start_offset];
+        slp.extend_from_slice(event_sizes_bytes);
+        let (event_sizes, _) = event_sizes(event_sizes_bytes).ok_or(DecompError::InvalidFile)?;
+        slp.extend_from_slice(&slpz[game_start_offset..metadata_offset]);
+
+        self.decompress_events(slpz, &header)?;
+        unorder_events(&self.reordered, &event_sizes, slp)?;
+
+        let metadata_offset_in_slp = slp.len();
+        slp.extend_from_slice(&slpz[metadata_offset..compressed_events_offset]);
+
+        slp[11..15].copy_from_slice(&(metadata_offset_in_slp as u32 - 15).to_be_bytes()); // raw len
+
+        Ok(())
+    }
+
+    /// Decompresses the reordered events of an slpz file to `self.reordered`.
+    fn decompress_events(&mut self, slpz: &[u8], header: &Header) -> Result<(), DecompError> {
+        self.reordered.clear();
+        self.reordered.resize(header.decompressed_events_size, 0u8);
+        let written = self.ctx.decompress(&mut self.reordered[..], &slpz[header.compressed_events_offset..])
+            .map_err(|_| DecompError::DecompressionFailure)?;
+        self.reordered.truncate(written);
+        Ok(())
     }
 }
 
-/// Compresses an slp file to an slpz file.
-pub fn compress(compressor: &mut Compressor, slp: &[u8]) -> Result<Vec<u8>, CompError> {
-    if slp.len() < 16 { return Err(CompError::InvalidFile) }
-    if &slp[0..11] != &RAW_HEADER { return Err(CompError::InvalidFile) }
-
-    // get metadata
-    let raw_len = u32::from_be_bytes(slp[11..15].try_into().unwrap()) as usize;
-    let metadata_offset = 15+raw_len;
-    let metadata = &slp[metadata_offset..];
-
-    // get event sizes
-    if slp[15] != EVENT_PAYLOADS { return Err(CompError::InvalidFile) }
-    let (event_sizes, event_type_count) = event_sizes(&slp[15..]).ok_or(CompError::InvalidFile)?;
-    let event_sizes_size = 2+event_type_count*3;
-    let event_sizes_payload = &slp[15..][..event_sizes_size];
-
-    // get game start
-    let game_start_offset = 15 + event_sizes_size;
-    let game_start_size = event_sizes[GAME_START as usize] as usize + 1;
-    if slp.len() < game_start_offset+game_start_size { return Err(CompError::InvalidFile) }
-    if slp[game_start_offset] != GAME_START { return Err(CompError::InvalidFile) }
-    let game_start_payload = &slp[game_start_offset..][..game_start_size];
-
-    let mut slpz = Vec::with_capacity(slp.len());
-
-    // header
-    slpz.extend_from_slice(&VERSION.to_be_bytes());
-    slpz.extend_from_slice(&[0u8; 20]); // offsets filled later
-
-    // write event sizes
-    let len = slpz.len() as u32;
-    slpz[4..8].copy_from_slice(&len.to_be_bytes());
-    slpz.extend_from_slice(event_sizes_payload);
-
-    // write game start
-    let len = slpz.len() as u32;
-    slpz[8..12].copy_from_slice(&len.to_be_bytes());
-    slpz.extend_from_slice(game_start_payload);
-
-    // write metadata
-    let len = slpz.len() as u32;
-    slpz[12..16].copy_from_slice(&len.to_be_bytes());
-    slpz.extend_from_slice(metadata);
-
-    // write compressed events
-    let len = slpz.len() as u32;
-    slpz[16..20].copy_from_slice(&len.to_be_bytes());
-
-    let other_events_offset = game_start_offset+game_start_size;
-    let mut reordered_data = Vec::with_capacity(slp.len());
-    let written = reorder_events(&slp[other_events_offset..metadata_offset], &event_sizes, &mut reordered_data)?;
-    slpz[20..24].copy_from_slice(&(written as u32).to_be_bytes());
-
-    // wrap in cursor so we don't overwrite previous data
-    let mut slpz_cursor = std::io::Cursor::new(slpz);
-    slpz_cursor.set_position(len as u64);
-    compressor.ctx.compress_to_buffer(&reordered_data, &mut slpz_cursor).map_err(|_| CompError::CompressionFailure)?;
-
-    Ok(slpz_cursor.into_inner())
+/// Section boundaries of an slp file. Offsets are from file start.
+struct SlpLayout {
+    event_sizes: [u16; 256],
+    /// The Event Payloads event starts at 15.
+    event_sizes_size: usize,
+    game_start_offset: usize,
+    game_start_size: usize,
+    metadata_offset: usize,
 }
 
-/// Decompresses an slpz file to an slp file.
-pub fn decompress(decompressor: &mut Decompressor, slpz: &[u8]) -> Result<Vec<u8>, DecompError> {
-    if slpz.len() < 24 { return Err(DecompError::InvalidFile) }
-    let version                  = u32::from_be_bytes(slpz[0..4].try_into().unwrap());
-    let event_sizes_offset       = u32::from_be_bytes(slpz[4..8].try_into().unwrap()) as usize;
-    let game_start_offset        = u32::from_be_bytes(slpz[8..12].try_into().unwrap()) as usize;
-    let metadata_offset          = u32::from_be_bytes(slpz[12..16].try_into().unwrap()) as usize;
-    let compressed_events_offset = u32::from_be_bytes(slpz[16..20].try_into().unwrap()) as usize;
-    let decompressed_events_size = u32::from_be_bytes(slpz[20..24].try_into().unwrap()) as usize;
+impl SlpLayout {
+    fn read(slp: &[u8]) -> Result<SlpLayout, CompError> {
+        if slp.len() < 16 { return Err(CompError::InvalidFile) }
+        if slp[0..11] != RAW_HEADER { return Err(CompError::InvalidFile) }
 
-    if slpz.len() < compressed_events_offset { return Err(DecompError::InvalidFile) }
+        // get metadata
+        let raw_len = u32::from_be_bytes(slp[11..15].try_into().unwrap()) as usize;
+        let metadata_offset = 15+raw_len;
 
-    // We do not return a custom version error here. 
-    // If a file is invalid, it would raise this error instead of an InvalidFile. 
-    // Unsupported version errors would be nice to check, but too many false positives.
-    if version > VERSION { return Err(DecompError::InvalidFile) }
+        // get event sizes
+        if slp[15] != EVENT_PAYLOADS { return Err(CompError::InvalidFile) }
+        let (event_sizes, event_type_count) = event_sizes(&slp[15..]).ok_or(CompError::InvalidFile)?;
+        let event_sizes_size = 2+event_type_count*3;
 
-    let mut slp = Vec::with_capacity(slpz.len() * 32);
-    slp.extend_from_slice(&RAW_HEADER);
-    slp.extend_from_slice(&[0u8; 4]); // raw len. filled in later
+        // get game start
+        let game_start_offset = 15 + event_sizes_size;
+        let game_start_size = event_sizes[GAME_START as usize] as usize + 1;
+        if slp.len() < game_start_offset+game_start_size { return Err(CompError::InvalidFile) }
+        if slp[game_start_offset] != GAME_START { return Err(CompError::InvalidFile) }
 
-    let event_sizes_bytes = &slpz[event_sizes_offset..game_start_offset];
-    slp.extend_from_slice(event_sizes_bytes);
-    let (event_sizes, _) = event_sizes(event_sizes_bytes).ok_or(DecompError::InvalidFile)?;
-    slp.extend_from_slice(&slpz[game_start_offset..metadata_offset]);
+        let in_order = game_start_offset + game_start_size <= metadata_offset && metadata_offset <= slp.len();
+        if !in_order { return Err(CompError::InvalidFile) }
 
-    let b = decompressor.ctx.decompress(&slpz[compressed_events_offset..], decompressed_events_size)
-        .map_err(|_| DecompError::DecompressionFailure)?;
-    unorder_events(&b, &event_sizes, &mut slp)?;
+        Ok(SlpLayout { event_sizes, event_sizes_size, game_start_offset, game_start_size, metadata_offset })
+    }
+}
 
-    let metadata_offset_in_slp = slp.len();
-    slp.extend_from_slice(&slpz[metadata_offset..compressed_events_offset]);
+/// The header at the start of every slpz file.
+///
+/// Offsets are from file start.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Header {
+    pub version: u32,
+    pub event_sizes_offset: usize,
+    pub game_start_offset: usize,
+    pub metadata_offset: usize,
+    pub compressed_events_offset: usize,
+    /// Size of the reordered events after zstd decompression.
+    pub decompressed_events_size: usize,
+}
+
+impl Header {
+    /// Reads and validates the header of an slpz file.
+    pub fn read(slpz: &[u8]) -> Result<Header, DecompError> {
+        if slpz.len() < HEADER_SIZE { return Err(DecompError::InvalidFile) }
+        let header = Header {
+            version:                  u32::from_be_bytes(slpz[0..4].try_into().unwrap()),
+            event_sizes_offset:       u32::from_be_bytes(slpz[4..8].try_into().unwrap()) as usize,
+            game_start_offset:        u32::from_be_bytes(slpz[8..12].try_into().unwrap()) as usize,
+            metadata_offset:          u32::from_be_bytes(slpz[12..16].try_into().unwrap()) as usize,
+            compressed_events_offset: u32::from_be_bytes(slpz[16..20].try_into().unwrap()) as usize,
+            decompressed_events_size: u32::from_be_bytes(slpz[20..24].try_into().unwrap()) as usize,
+        };
+
+        // We do not return a custom version error here. 
+        // If a file is invalid, it would raise this error instead of an InvalidFile. 
+        // Unsupported version errors would be nice to check, but too many false positives.
+        if header.version > VERSION { return Err(DecompError::InvalidFile) }
+
+        let in_order = HEADER_SIZE <= header.event_sizes_offset
+            && header.event_sizes_offset <= header.game_start_offset
+            && header.game_start_offset <= header.metadata_offset
+            && header.metadata_offset <= header.compressed_events_offset
+            && header.compressed_events_offset <= slpz.len();
+        if !in_order { return Err(DecompError::InvalidFile) }
 
-    slp[11..15].copy_from_slice(&(metadata_offset_in_slp as u32 - 15).to_be_bytes()); // raw len
+        // the reordered events start with the 4 byte event count
+        if header.decompressed_events_size < 4 { return Err(DecompError::InvalidFile) }
 
+        Ok(header)
+    }
+
+    /// The exact size of the slp file this slpz file decompresses to.
+    pub fn decompressed_size(&self) -> usize {
+        let sections_size = self.compressed_events_offset - self.event_sizes_offset;
+        let events_size = self.decompressed_events_size - 4; // event count is not in the slp file
+        RAW_HEADER.len() + 4 + sections_size + events_size
+    }
+}
+
+/// Returns the exact size of the slp file an slpz file decompresses to, without decompressing it.
+pub fn decompressed_size(slpz: &[u8]) -> Result<usize, DecompError> {
+    Ok(Header::read(slpz)?.decompressed_size())
+}
+
+/// The format of a file, as detected by `detect_format`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Slp,
+    Slpz,
+    Unknown,
+}
+
+/// Detects whether a file is an slp or slpz file from its contents.
+///
+/// Only the first 25 bytes are checked, so passing the start of a file is enough.
+/// The file is not fully validated, so compression or decompression may still fail.
+pub fn detect_format(file: &[u8]) -> Format {
+    if file.len() > 15 && file[0..11] == RAW_HEADER && file[15] == EVENT_PAYLOADS { return Format::Slp }
+
+    if file.len() > HEADER_SIZE {
+        let word = |i: usize| u32::from_be_bytes(file[i..i+4].try_into().unwrap());
+        if word(0) > VERSION { return Format::Unknown }
+        let valid = word(4) == HEADER_SIZE as u32
+            && word(4) <= word(8)
+            && word(8) <= word(12)
+            && word(12) <= word(16)
+            && word(20) >= 4
+            && file[HEADER_SIZE] == EVENT_PAYLOADS;
+        if valid { return Format::Slpz }
+    }
+
+    Format::Unknown
+}
+
+/// The uncompressed sections of an slp or slpz file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sections<'a> {
+    /// The Game Start event, including the command byte.
+    pub game_start: &'a [u8],
+    /// The UBJSON 'metadata' key and value followed by the closing brace of the slp file.
+    /// See `ubjson::read_metadata`.
+    pub metadata: &'a [u8],
+}
+
+/// Returns the Game Start and metadata sections of an slp or slpz file.
+pub fn sections(file: &[u8]) -> Result<Sections<'_>, EditError> {
+    match detect_format(file) {
+        Format::Slp => {
+            let layout = SlpLayout::read(file).map_err(|_| EditError::InvalidFile)?;
+            Ok(Sections {
+                game_start: &file[layout.game_start_offset..][..layout.game_start_size],
+                metadata: &file[layout.metadata_offset..],
+            })
+        }
+        Format::Slpz => {
+            let header = Header::read(file).map_err(|_| EditError::InvalidFile)?;
+            Ok(Sections {
+                game_start: &file[header.game_start_offset..header.metadata_offset],
+                metadata: &file[header.metadata_offset..header.compressed_events_offset],
+            })
+        }
+        Format::Unknown => Err(EditError::InvalidFile),
+    }
+}
+
+/// Replaces the Game Start and metadata sections of an slp or slpz file. See `sections`.
+///
+/// Events are copied as they are, so slpz files are not recompressed.
+/// Game Start must keep its size.
+pub fn replace_sections(file: &[u8], game_start: &[u8], metadata: &[u8]) -> Result<Vec<u8>, EditError> {
+    let old = sections(file)?;
+    if game_start.len() != old.game_start.len() || game_start.first() != Some(&GAME_START) { 
+        return Err(EditError::GameStartSize)
+    }
+
+    let mut out = Vec::with_capacity(file.len() - old.metadata.len() + metadata.len());
+    match detect_format(file) {
+        Format::Slp => {
+            let layout = SlpLayout::read(file).map_err(|_| EditError::InvalidFile)?;
+            let game_start_end = layout.game_start_offset + layout.game_start_size;
+            out.extend_from_slice(&file[..layout.game_start_offset]);
+            out.extend_from_slice(game_start);
+            out.extend_from_slice(&file[game_start_end..layout.metadata_offset]);
+            out.extend_from_slice(metadata);
+        }
+        _ => {
+            let header = Header::read(file).map_err(|_| EditError::InvalidFile)?;
+            let metadata_offset = header.metadata_offset;
+            let compressed_events_offset = metadata_offset + metadata.len();
+            if compressed_events_offset > u32::MAX as usize { return Err(EditError::MetadataSize) }
+
+            out.extend_from_slice(&file[..header.game_start_offset]);
+            out[16..20].copy_from_slice(&(compressed_events_offset as u32).to_be_bytes());
+            out.extend_from_slice(game_start);
+            out.extend_from_slice(metadata);
+            out.extend_from_slice(&file[header.compressed_events_offset..]);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Edits the Game Start event and the parsed metadata of an slp or slpz file,
+/// then writes them back with `replace_sections`.
+pub fn edit_sections(
+    file: &[u8], 
+    edit: impl FnOnce(&mut [u8], &mut ubjson::Value),
+) -> Result<Vec<u8>, EditError> {
+    let old = sections(file)?;
+    let mut game_start = old.game_start.to_vec();
+    let mut metadata = ubjson::read_metadata(old.metadata).ok_or(EditError::InvalidMetadata)?;
+
+    edit(&mut game_start, &mut metadata);
+
+    let mut metadata_section = Vec::with_capacity(old.metadata.len());
+    ubjson::write_metadata(&metadata, &mut metadata_section);
+    replace_sections(file, &game_start, &metadata_section)
+}
+
+/// Compresses an slp file to an slpz file.
+pub fn compress(compressor: &mut Compressor, slp: &[u8]) -> Result<Vec<u8>, CompError> {
+    let mut slpz = Vec::with_capacity(compress_bound(slp.len()));
+    compressor.compress_into(slp, &mut slpz)?;
+    Ok(slpz)
+}
+
+/// The most `compress_into` can need for an slp file of this size, including the space zstd reserves.
+fn compress_bound(slp_len: usize) -> usize {
+    // sections are copied from the slp file, events are at most the slp file plus the event count
+    HEADER_SIZE + slp_len + zstd_safe::compress_bound(slp_len + 4)
+}
+
+/// Decompresses an slpz file to an slp file.
+pub fn decompress(decompressor: &mut Decompressor, slpz: &[u8]) -> Result<Vec<u8>, DecompError> {
+    let mut slp = Vec::with_capacity(decompressed_size(slpz)?);
+    decompressor.decompress_into(slpz, &mut slp)?;
     Ok(slp)
 }
 
@@ -271,10 +633,12 @@ fn unorder_events(
     event_sizes: &[u16; 256], 
     buf: &mut Vec<u8>,
 ) -> Result<usize, DecompError> {
-    let total_events = u32::from_be_bytes(b[0..4].try_into().unwrap()) as usize;
+    let total_events = b.get(0..4).ok_or(DecompError::InvalidFile)?;
+    let total_events = u32::from_be_bytes(total_events.try_into().unwrap()) as usize;
 
     let event_order_list_offset = 4;
     let reordered_events_offset = event_order_list_offset + total_events;
+    if reordered_events_offset > b.len() { return Err(DecompError::InvalidFile) }
 
     let mut event_counts = [0u32; 256];
     for i in 0..total_events {
@@ -282,22 +646,24 @@ fn unorder_events(
         event_counts[event] += 1;
     }
 
-    let mut reordered_event_offsets = [0u32; 256];
+    let mut reordered_event_offsets = [0usize; 256];
     for i in 0..255 {
         let size = event_sizes[i];
         let count = event_counts[i];
         
-        let event_total_size = size as u32 * count;
+        let event_total_size = size as usize * count as usize;
 
         // offset for next event is the end of this event.
-        reordered_event_offsets[i+1] = reordered_event_offsets[i] + event_total_size;
+        reordered_event_offsets[i+1] = reordered_event_offsets[i].checked_add(event_total_size)
+            .ok_or(DecompError::InvalidFile)?;
     }
 
     let unordered_size = {
         let last_size = event_sizes[255];
         let last_count = event_counts[255];
         let last_total_size = last_count as usize * last_size as usize;
-        reordered_event_offsets[255] as usize + last_total_size + total_events
+        reordered_event_offsets[255].checked_add(last_total_size + total_events)
+            .ok_or(DecompError::InvalidFile)?
     };
 
     let event_order_list = &b[event_order_list_offset..reordered_events_offset];
@@ -320,7 +686,7 @@ fn unorder_events(
         data[data_i] = event_u8;
 
         // unorder data
-        let event_offset = reordered_event_offsets[event] as usize;
+        let event_offset = reordered_event_offsets[event];
         let written = events_written[event] as usize;
         let size = event_sizes[event] as usize;
         let stride = event_counts[event] as usize;
@@ -339,13 +705,13 @@ fn unorder_events(
 }
 
 fn event_sizes(events: &[u8]) -> Option<([u16; 256], usize)> {
-    if events.is_empty() { return None }
+    if events.len() < 2 { return None }
 
+    // the size includes itself, and is followed by 3 bytes for each command
     let info_size = events[1] as usize;
+    if info_size == 0 || events.len() < 1 + info_size { return None }
     let event_count = (info_size - 1) / 3;
 
-    if events.len() < info_size { return None }
-
     let mut event_payload_sizes = [0; 256];
     for i in 0..event_count {
         let offset = i*3 + 2;
@@ -372,247 +738,132 @@ fn event_counts(events: &[u8], event_sizes: &[u16; 256]) -> Result<[u32; 256], C
     Ok(counts)
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct Options {
-    pub keep: bool,
-    pub compress: Option<bool>,
-    pub recursive: bool,
-    pub threading: bool,
-    /// must be between 1 and 19.
-    pub level: i32,
-    pub log: bool,
-}
-
-impl Default for Options {
-    fn default() -> Self { Options::DEFAULT }
-}
-
-impl Options {
-    pub const DEFAULT: Self = Options {
-        keep: true,
-        compress: None,
-        recursive: false,
-        threading: true,
-        level: 3,
-        log: true,
-    };
-}
-
-/// Library access to slpz program functionality.
-///
-/// If Some, the sender will first send the number of targets.
-/// After that, the sender will send '1' for each target completed.
-/// If the sender cannot send, it will panic.
-///
-/// - Threaded directory compression/decompression.
-/// - Compression/decompression autodetection.
-/// - Deletion of old files.
-pub fn target_path(
-    options: &Options,
-    path: &std::path::Path,
-    sender: Option<std::sync::mpsc::Sender<usize>>,
-) -> Result<(), TargetPathError> {
-    if !matches!(path.try_exists(), Ok(true)) { return Err(TargetPathError::PathNotFound) }
-    
-    let mut targets = Vec::new();
-    let mut should_compress = options.compress;
-
-    if path.is_dir() {
-        let c = match should_compress {
-            Some(c) => c,
-            None => return Err(TargetPathError::CompressOrDecompressAmbiguous),
-        };
-        let ex = std::ffi::OsStr::new(if c { "slp" } else { "slpz" });
-        get_targets(&mut targets, &path, options.recursive, ex);
-    } else if path.is_file() {
-        targets.push(path.to_path_buf());
-        if should_compress == None {
-            let ex = path.extension();
-            if ex == Some(std::ffi::OsStr::new("slp")) {
-                should_compress = Some(true);
-            } else if ex == Some(std::ffi::OsStr::new("slpz")) {
-                should_compress = Some(false);
-            }
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const GAME_START_SIZE: u16 = 0x300;
+    const PRE_FRAME_SIZE: u16 = 0x3F;
+    const POST_FRAME_SIZE: u16 = 0x48;
+    const FRAME_START_SIZE: u16 = 0xC;
+    const FRAME_BOOKEND_SIZE: u16 = 0x8;
+    const GAME_END_SIZE: u16 = 0x2;
+
+    /// An slp file with frames `first_frame..=last_frame`, each a Frame Start,
+    /// a Pre-Frame and Post-Frame Update for one player and a Frame Bookend, followed by Game End.
+    pub fn replay(first_frame: i32, last_frame: i32) -> Vec<u8> {
+        let mut slp = RAW_HEADER.to_vec();
+        slp.extend_from_slice(&[0; 4]);
+
+        let sizes = [
+            (GAME_START, GAME_START_SIZE),
+            (PRE_FRAME_UPDATE, PRE_FRAME_SIZE),
+            (POST_FRAME_UPDATE, POST_FRAME_SIZE),
+            (GAME_END, GAME_END_SIZE),
+            (FRAME_START, FRAME_START_SIZE),
+            (FRAME_BOOKEND, FRAME_BOOKEND_SIZE),
+        ];
+        slp.push(EVENT_PAYLOADS);
+        slp.push(1 + 3 * sizes.len() as u8);
+        for (command, size) in sizes {
+            slp.push(command);
+            slp.extend_from_slice(&size.to_be_bytes());
         }
-    } else {
-        return Err(TargetPathError::PathInvalid);
-    }
-
-    let will_compress = match should_compress {
-        Some(n) => n,
-        None => return Err(TargetPathError::CompressOrDecompressAmbiguous),
-    };
-
-    if let Some(ref sender) = sender { sender.send(targets.len()).expect("Sending failed"); }
 
-    if !options.threading || targets.len() < 8 {
-        if will_compress {
-            let mut compressor = Compressor::new(options.level).ok_or(TargetPathError::ZstdInitError)?;
-            for t in targets.iter() { 
-                compress_target(&mut compressor, options, t); 
-                if let Some(ref sender) = sender { sender.send(1).expect("Sending failed"); }
+        slp.push(GAME_START);
+        slp.extend((0..GAME_START_SIZE).map(|i| i as u8));
+
+        for frame in first_frame..=last_frame {
+            for (command, size) in [
+                (FRAME_START, FRAME_START_SIZE),
+                (PRE_FRAME_UPDATE, PRE_FRAME_SIZE),
+                (POST_FRAME_UPDATE, POST_FRAME_SIZE),
+                (FRAME_BOOKEND, FRAME_BOOKEND_SIZE),
+            ] {
+                slp.push(command);
+                slp.extend_from_slice(&frame.to_be_bytes());
+                slp.extend((4..size).map(|i| (frame as u16 ^ i) as u8));
             }
-        } else {
-            let mut decompressor = Decompressor::new().ok_or(TargetPathError::ZstdInitError)?;
-            for t in targets.iter() { 
-                decompress_target(&mut decompressor, options, t); 
-                if let Some(ref sender) = sender { sender.send(1).expect("Sending failed"); }
-            }
-        }
-    } else {
-        // split into 8 approximately equal slices (why is this so annoying?)
-        let mut slices: [&[std::path::PathBuf]; 8] = [&[]; 8];
-        let chunk = targets.len() / 8;
-        let split = (chunk + 1) * (targets.len() % 8);
-        for (i, c) in targets[..split].chunks(chunk+1).chain(targets[split..].chunks(chunk)).enumerate() {
-            slices[i] = c;
         }
+        slp.extend_from_slice(&[GAME_END, 2, 0]);
 
-        let sender_ref = sender.as_ref();
-
-        std::thread::scope(|scope| {
-            if will_compress {
-                for s in slices {
-                    scope.spawn(move || {
-                        let sender = sender_ref.clone();
-                        let mut compressor = match Compressor::new(options.level) {
-                            Some(c) => c,
-                            None => {
-                                eprintln!("Error: Failed to init zstd compressor");
-                                return;
-                            }
-                        };
-                        for t in s { 
-                            compress_target(&mut compressor, options, t); 
-                            if let Some(ref sender) = sender { sender.send(1).expect("Sending failed"); }
-                        }
-                    });
-                }
-            } else {
-                for s in slices {
-                    scope.spawn(move || {
-                        let sender = sender_ref.clone();
-                        let mut decompressor = match Decompressor::new() {
-                            Some(d) => d,
-                            None => {
-                                eprintln!("Error: Failed to init zstd decompressor");
-                                return;
-                            }
-                        };
-                        for t in s { 
-                            decompress_target(&mut decompressor, options, t); 
-                            if let Some(ref sender) = sender { sender.send(1).expect("Sending failed"); }
-                        }
-                    });
-                }
-            };
-        })
+        let raw_len = slp.len() - 15;
+        slp[11..15].copy_from_slice(&(raw_len as u32).to_be_bytes());
+
+        let metadata = ubjson::Value::Object(vec![
+            ("startAt".into(), ubjson::Value::String("2024-01-01T00:00:00Z".into())),
+            ("lastFrame".into(), ubjson::Value::I32(last_frame)),
+        ]);
+        ubjson::write_metadata(&metadata, &mut slp);
+        slp
     }
-    
-    Ok(())
-}
 
-fn compress_target(c: &mut Compressor, options: &Options, t: &std::path::PathBuf) {
-    let slp = match std::fs::read(&t) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Error compressing {}: {}", t.display(), e);
-            return;
-        }
-    };
-    
-    match compress(c, &slp) {
-        Ok(slpz) => {
-            let mut out = t.clone();
-            if !out.set_extension("slpz") { 
-                eprintln!("Error creating new filename for {}", t.display());
-                return;
-            };
-            match std::fs::write(&out, &slpz) {
-                Ok(_) => {
-                    if options.log { println!("compressed {}", t.display()); }
-                    if !options.keep {
-                        match std::fs::remove_file(&t) {
-                            Ok(_) => if options.log { println!("removed {}", t.display()) },
-                            Err(e) => {
-                                eprintln!("Error removing {}: {}", t.display(), e);
-                                return;
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Error compressing {}: {}", t.display(), e);
-                    return;
-                },
-            };
-        }
-        Err(e) => {
-            eprintln!("Error compressing {}: {}", t.display(), e);
-            return;
-        }
+    /// Replaces the compressed events of an slpz file with `reordered`.
+    fn with_events(slpz: &[u8], reordered: &[u8]) -> Vec<u8> {
+        let header = Header::read(slpz).unwrap();
+        let mut out = slpz[..header.compressed_events_offset].to_vec();
+        out[20..24].copy_from_slice(&(reordered.len().max(4) as u32).to_be_bytes());
+
+        let mut compressed = vec![0u8; zstd_safe::compress_bound(reordered.len())];
+        let size = zstd_safe::compress(&mut compressed[..], reordered, 1).unwrap();
+        out.extend_from_slice(&compressed[..size]);
+        out
     }
-}
 
-fn decompress_target(d: &mut Decompressor, options: &Options, t: &std::path::PathBuf) {
-    let slpz = match std::fs::read(&t) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Error decompressing {}: {}", t.display(), e);
-            return;
-        }
-    };
-    
-    match decompress(d, &slpz) {
-        Ok(slp) => {
-            let mut out = t.clone();
-            if !out.set_extension("slp") { 
-                eprintln!("Error creating new filename for {}", t.display());
-                return; 
-            };
-            match std::fs::write(&out, &slp) {
-                Ok(_) => {
-                    if options.log { println!("decompressed {}", t.display()); }
-                    if !options.keep {
-                        match std::fs::remove_file(&t) {
-                            Ok(_) => if options.log { println!("removed {}", t.display()) },
-                            Err(e) => {
-                                eprintln!("Error removing {}: {}", t.display(), e);
-                                return;
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Error decompressing {}: {}", t.display(), e);
-                    return;
-                }
-            };
-        }
-        Err(e) => {
-            eprintln!("Error decompressing {}: {}", t.display(), e);
-            return;
-        }
+    #[test]
+    fn round_trip() {
+        let slp = replay(-123, 200);
+        let slpz = compress(&mut Compressor::new(3).unwrap(), &slp).unwrap();
+        assert_eq!(slpz.capacity(), compress_bound(slp.len()), "compress reallocated");
+        assert_eq!(decompressed_size(&slpz), Ok(slp.len()));
+        assert_eq!(decompress(&mut Decompressor::new().unwrap(), &slpz), Ok(slp));
     }
-}
 
-fn get_targets(
-    targets: &mut Vec<std::path::PathBuf>, 
-    path: &std::path::Path, 
-    rec: bool, 
-    ex: &std::ffi::OsStr,
-) -> Option<()> {
-    for f in std::fs::read_dir(path).ok()? {
-        let f = match f {
-            Ok(f) => f,
-            Err(_) => continue,
+    #[test]
+    fn header_offsets() {
+        let slpz = compress(&mut Compressor::new(3).unwrap(), &replay(0, 10)).unwrap();
+        let header = Header::read(&slpz).unwrap();
+        assert_eq!(header.event_sizes_offset, HEADER_SIZE);
+        assert!(Header::read(&slpz[..HEADER_SIZE - 1]).is_err());
+
+        let with_word = |i: usize, word: usize| {
+            let mut slpz = slpz.clone();
+            slpz[i..i + 4].copy_from_slice(&(word as u32).to_be_bytes());
+            Header::read(&slpz)
         };
+        assert_eq!(with_word(0, VERSION as usize + 1), Err(DecompError::InvalidFile));
+        assert_eq!(with_word(4, HEADER_SIZE - 1), Err(DecompError::InvalidFile));
+        assert_eq!(with_word(8, header.event_sizes_offset - 1), Err(DecompError::InvalidFile));
+        assert_eq!(with_word(12, header.game_start_offset - 1), Err(DecompError::InvalidFile));
+        assert_eq!(with_word(16, header.metadata_offset - 1), Err(DecompError::InvalidFile));
+        assert_eq!(with_word(16, slpz.len() + 1), Err(DecompError::InvalidFile));
+        assert_eq!(with_word(20, 3), Err(DecompError::InvalidFile));
+    }
 
-        let path = f.path();
+    #[test]
+    fn crafted_events() {
+        let slpz = compress(&mut Compressor::new(3).unwrap(), &replay(0, 10)).unwrap();
+        let mut decompressor = Decompressor::new().unwrap();
 
-        if rec && path.is_dir() { get_targets(targets, &path, rec, ex); }
-        if path.is_file() && path.extension() == Some(ex) { targets.push(path)}
+        let mut too_many_events = vec![0, 0, 1, 0, PRE_FRAME_UPDATE];
+        too_many_events.extend_from_slice(&[0; PRE_FRAME_SIZE as usize]);
+        for reordered in [&[][..], &[0, 0], &[0xFF, 0xFF, 0xFF, 0xFF], &too_many_events] {
+            let crafted = with_events(&slpz, reordered);
+            assert_eq!(decompress(&mut decompressor, &crafted), Err(DecompError::InvalidFile));
+        }
     }
 
-    Some(())
+    #[test]
+    fn crafted_event_sizes() {
+        let slpz = compress(&mut Compressor::new(3).unwrap(), &replay(0, 10)).unwrap();
+        let mut decompressor = Decompressor::new().unwrap();
+
+        // Event Payloads with a size of zero, and with a size past its end
+        for info_size in [0, 0xFF] {
+            let mut crafted = slpz.clone();
+            crafted[HEADER_SIZE + 1] = info_size;
+            assert_eq!(decompress(&mut decompressor, &crafted), Err(DecompError::InvalidFile));
+        }
+    }
 }
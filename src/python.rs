@@ -0,0 +1,153 @@
+//! Python bindings.
+//!
+//! Build with maturin: `maturin develop --release`. See `pyproject.toml`.
+//!
+//! Compression and decompression release the GIL,
+//! so `Compressor`s and `Decompressor`s can be used from multiple Python threads.
+
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use pyo3::exceptions::PyException;
+
+pyo3::create_exception!(slpz, CompError, PyException, "Raised when compressing an slp file fails.");
+pyo3::create_exception!(slpz, DecompError, PyException, "Raised when decompressing an slpz file fails.");
+
+fn comp_err(e: crate::CompError) -> PyErr { CompError::new_err(e.to_string()) }
+fn decomp_err(e: crate::DecompError) -> PyErr { DecompError::new_err(e.to_string()) }
+
+/// Reusable compression context.
+#[pyclass(name = "Compressor", module = "slpz")]
+struct PyCompressor { compressor: crate::Compressor }
+
+/// Reusable decompression context.
+#[pyclass(name = "Decompressor", module = "slpz")]
+struct PyDecompressor { decompressor: crate::Decompressor }
+
+#[pymethods]
+impl PyCompressor {
+    /// level should be between 1 and 19. The default is 3.
+    #[new]
+    #[pyo3(signature = (level=3))]
+    fn new(level: i32) -> PyResult<Self> {
+        let compressor = crate::Compressor::new(level)
+            .ok_or_else(|| CompError::new_err("Failed to init zstd"))?;
+        Ok(PyCompressor { compressor })
+    }
+
+    /// Compresses an slp file to an slpz file.
+    fn compress<'py>(&mut self, py: Python<'py>, slp: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let compressor = &mut self.compressor;
+        let slpz = py.detach(|| crate::compress(compressor, slp)).map_err(comp_err)?;
+        Ok(PyBytes::new(py, &slpz))
+    }
+}
+
+#[pymethods]
+impl PyDecompressor {
+    #[new]
+    fn new() -> PyResult<Self> {
+        let decompressor = crate::Decompressor::new()
+            .ok_or_else(|| DecompError::new_err("Failed to init zstd"))?;
+        Ok(PyDecompressor { decompressor })
+    }
+
+    /// Decompresses an slpz file to an slp file.
+    fn decompress<'py>(&mut self, py: Python<'py>, slpz: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+        let decompressor = &mut self.decompressor;
+        let slp = py.detach(|| crate::decompress(decompressor, slpz)).map_err(decomp_err)?;
+        Ok(PyBytes::new(py, &slp))
+    }
+}
+
+/// The header at the start of every slpz file. Offsets are from file start.
+#[pyclass(name = "Header", module = "slpz", frozen, get_all)]
+struct PyHeader {
+    version: u32,
+    event_sizes_offset: usize,
+    game_start_offset: usize,
+    metadata_offset: usize,
+    compressed_events_offset: usize,
+    decompressed_events_size: usize,
+    /// The exact size of the slp file this slpz file decompresses to.
+    decompressed_size: usize,
+}
+
+#[pymethods]
+impl PyHeader {
+    fn __repr__(&self) -> String {
+        format!(
+            "Header(version={}, event_sizes_offset={}, game_start_offset={}, metadata_offset={}, \
+            compressed_events_offset={}, decompressed_events_size={}, decompressed_size={})",
+            self.version, self.event_sizes_offset, self.game_start_offset, self.metadata_offset,
+            self.compressed_events_offset, self.decompressed_events_size, self.decompressed_size,
+        )
+    }
+}
+
+/// Compresses an slp file to an slpz file.
+#[pyfunction]
+#[pyo3(signature = (slp, level=3))]
+fn compress<'py>(py: Python<'py>, slp: &[u8], level: i32) -> PyResult<Bound<'py, PyBytes>> {
+    PyCompressor::new(level)?.compress(py, slp)
+}
+
+/// Decompresses an slpz file to an slp file.
+#[pyfunction]
+fn decompress<'py>(py: Python<'py>, slpz: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    PyDecompressor::new()?.decompress(py, slpz)
+}
+
+/// Reads and validates the header of an slpz file.
+#[pyfunction]
+fn read_header(slpz: &[u8]) -> PyResult<PyHeader> {
+    let header = crate::Header::read(slpz).map_err(decomp_err)?;
+    Ok(PyHeader {
+        version: header.version,
+        event_sizes_offset: header.event_sizes_offset,
+        game_start_offset: header.game_start_offset,
+        metadata_offset: header.metadata_offset,
+        compressed_events_offset: header.compressed_events_offset,
+        decompressed_events_size: header.decompressed_events_size,
+        decompressed_size: header.decompressed_size(),
+    })
+}
+
+/// Returns the exact size of the slp file an slpz file decompresses to, without decompressing it.
+#[pyfunction]
+fn decompressed_size(slpz: &[u8]) -> PyResult<usize> {
+    crate::decompressed_size(slpz).map_err(decomp_err)
+}
+
+/// Returns the uncompressed Game Start event of an slpz file, including the command byte.
+#[pyfunction]
+fn game_start<'py>(py: Python<'py>, slpz: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let header = crate::Header::read(slpz).map_err(decomp_err)?;
+    Ok(PyBytes::new(py, &slpz[header.game_start_offset..header.metadata_offset]))
+}
+
+/// Returns the uncompressed metadata of an slpz file.
+///
+/// This is the UBJSON 'metadata' key and value followed by the closing brace of the slp file, 
+/// so `ubjson.loadb(b'{' + metadata(slpz))` parses it.
+#[pyfunction]
+fn metadata<'py>(py: Python<'py>, slpz: &[u8]) -> PyResult<Bound<'py, PyBytes>> {
+    let header = crate::Header::read(slpz).map_err(decomp_err)?;
+    Ok(PyBytes::new(py, &slpz[header.metadata_offset..header.compressed_events_offset]))
+}
+
+#[pymodule]
+fn slpz(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("VERSION", crate::VERSION)?;
+    m.add("CompError", m.py().get_type::<CompError>())?;
+    m.add("DecompError", m.py().get_type::<DecompError>())?;
+    m.add_class::<PyCompressor>()?;
+    m.add_class::<PyDecompressor>()?;
+    m.add_class::<PyHeader>()?;
+    m.add_function(wrap_pyfunction!(compress, m)?)?;
+    m.add_function(wrap_pyfunction!(decompress, m)?)?;
+    m.add_function(wrap_pyfunction!(read_header, m)?)?;
+    m.add_function(wrap_pyfunction!(decompressed_size, m)?)?;
+    m.add_function(wrap_pyfunction!(game_start, m)?)?;
+    m.add_function(wrap_pyfunction!(metadata, m)?)?;
+    Ok(())
+}
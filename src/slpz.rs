@@ -2,6 +2,41 @@ use slpz::*;
 
 const HELP: &'static str =
 "Usage: slpz [OPTIONS] <input path>
+       slpz pack [PACK OPTIONS] <archive> <input path>
+       slpz append [PACK OPTIONS] <archive> <input path>
+       slpz unpack <archive> <output dir>
+       slpz list <archive>
+       slpz compact <archive>
+       slpz extract [-o <output path>] <archive> <name>
+       slpz edit [EDIT OPTIONS] <input path>
+       slpz anonymize [ANONYMIZE OPTIONS] <input path>
+       slpz organize --template <template> [ORGANIZE OPTIONS] <input path>
+       slpz dedupe [DEDUPE OPTIONS] <input path>
+       slpz trim [TRIM OPTIONS] <input file>
+       slpz export --format parquet [-r] -o <output dir> <input path>
+       slpz dump --json [--events] <input file>
+
+Commands:
+  pack                  Compress replays into a single slpzar archive.
+  append                Compress replays into an existing archive. 
+                          Replays already in the archive are skipped.
+  unpack                Decompress every replay in an archive into a directory.
+  list                  List the replays in an archive.
+  compact               Remove the unused space that each append leaves in an archive.
+  extract               Decompress a single replay from an archive.
+  edit                  Change the metadata and player names of slp and slpz files in place,
+                          without recompressing them.
+  anonymize             Replace player names and connect codes of slp and slpz files in place
+                          with pseudonyms, and remove the console nickname.
+  organize              Move slp and slpz files to paths filled in from their players, stage and date.
+  dedupe                Find slp and slpz files of the same game.
+  trim                  Cut an slp or slpz file down to a range of frames, as a new replay.
+  export                Write the events of slp and slpz files to a Parquet table for each event type,
+                          with a column for each field of core events. Requires the parquet feature.
+  dump                  Print the structure of an slp or slpz file as JSON: headers, event payload sizes,
+                          Game Start, metadata, and the number of events of each command.
+                          With --events, every event with its offset and hex payload instead.
+                          If the file is invalid, prints where parsing stopped and why.
 
 Options:
   --fast                Prefer speed over compression [Default]
@@ -9,11 +44,85 @@ Options:
   -x, --compress        
   -d, --decompress      
   -r, --recursive       Compress/decompress all files in subdirectories.
+  -P, --no-follow       Do not follow symlinks in directories.
+  --one-file-system     Do not recurse into directories on other filesystems.
+  --sniff               Choose files in directories by their contents instead of their extension.
+  --skip-hidden         Skip files and directories starting with '.'.
+  -j, --jobs <n>        Compress single files with n threads. Requires the zstdmt feature.
   -k, --keep            Keep files after compression/decompression. [Default]
   --rm                  Remove files after compression/decompression.
+  --existing=<policy>   What to do if the output file already exists:
+                          overwrite   Replace it. [Default]
+                          skip        Leave it and skip the file.
+                          error       Leave it and report an error.
+                          if-newer    Replace it if the input file was modified more recently.
+  --include <glob>      Only target files in directories matching the glob. Can be repeated.
+  --exclude <glob>      Do not target files in directories matching the glob. Can be repeated.
+                          Globs match paths relative to the input path, or only file names
+                          if they have no '/'. '*' and '?' stay within a directory, '**' does not.
+  --older-than <age>    Only target files in directories modified more than age ago.
+                          e.g. 30d, 12h, 90m, 2w.
+  --min-size <size>     Only target files in directories of at least size bytes.
+                          e.g. 500k, 2M.
+  --no-preserve         Do not copy timestamps and permissions to new files.
   -q, --quiet           Do not log to stdout.
   -h, --help
-  -v, --version";
+  -v, --version
+
+Pack options:
+  --fast, --small, -r, -q, --include, --exclude, --older-than, --min-size  As above.
+  -P, --no-follow       As above.
+  --skip-hidden         As above.
+  --dict                Train a shared dictionary on the replays. Helps archives of many short replays.
+                          Only for pack, appended replays use the archive's dictionary.
+
+Edit options:
+  -r, --recursive       Edit all files in subdirectories.
+  -o <output path>      Write the edited file here instead of editing in place. Only for single files.
+  --set <key>=<value>   Set a metadata string. Nested keys are separated with '.',
+                          e.g. --set players.0.names.netplay=Foo
+  --set-int <key>=<n>   Set a metadata integer.
+  --remove <key>        Remove a metadata value.
+  --start-at <date>     Set the date the replay was recorded, e.g. 2024-01-01T12:00:00Z.
+  --name <port>=<name>  Set the display name of a port (1 to 4) in Game Start and metadata.
+                          Names in Game Start must be ASCII and at most 30 characters.
+
+Anonymize options:
+  -r, --recursive       Anonymize all files in subdirectories.
+  -o <output path>      Write the anonymized file here instead of in place. Only for single files.
+  --salt <salt>         Players get the same pseudonym in every replay anonymized with the same salt.
+                          Use a secret salt, or pseudonyms can be traced back to known players.
+
+Organize options:
+  --template <template> Path relative to the output directory, with fields in braces,
+                          e.g. \"{date}/{p1_code}-vs-{p2_code}_{stage}.slpz\".
+                          Fields are date, time, stage, name (the current file name),
+                          and p1_code, p1_name and p1_char to p4_*, for players in port order.
+                          Missing fields are 'unknown'. Files keep their own extension.
+                          If a path is taken, a number is added, as in 'name_2.slpz'.
+  -r, --recursive       Organize all files in subdirectories.
+  -o <output dir>       Move files here instead of the input directory.
+  -n, --dry-run         Print moves without moving files.
+  --existing=<policy>   What to do if a path is taken, as above, instead of adding a number.
+  -P, --no-follow, --skip-hidden, -q, --include, --exclude, --older-than, --min-size  As above.
+
+Dedupe options:
+  -r, --recursive       Search all files in subdirectories.
+  --events              Compare events instead of Game Start. Slower, as slpz files are decompressed.
+                          By default, online games are compared by match ID and game number,
+                          and other games by Game Start without player names, which includes the random seed.
+  --remove              Remove duplicates, keeping the first file of each game.
+  --hardlink            Replace duplicates with hard links to an earlier file of the game
+                          with identical contents. Other duplicates are kept.
+                        Without --remove or --hardlink, duplicates are only listed.
+
+Trim options:
+  --from-frame <frame>  Frame the clip starts at. Frames start at -123, and the game starts at frame 0.
+                          Earlier frames are kept, as Dolphin plays replays from their first frame,
+                          and this frame is saved as 'startFrame' in the metadata.
+  --to-frame <frame>    Last frame to keep. Defaults to the last frame.
+  -o <output path>      Where to write the trimmed replay. Defaults to '<name>_trimmed.slp(z)'.
+  --fast, --small       As above, for slpz files.";
 
 macro_rules! unwrap_option {
     ($e:expr) => {
@@ -29,11 +138,32 @@ macro_rules! unwrap_option {
 
 fn main() {
     let mut options = Options::DEFAULT; 
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
 
     let mut arg_strings = std::env::args();
     arg_strings.next(); // skip exe name
     let mut arg_strings = arg_strings.collect::<Vec<_>>();
 
+    if arg_strings.len() > 1 {
+        match arg_strings[0].as_ref() {
+            "pack" => return pack(&arg_strings[1..], false),
+            "append" => return pack(&arg_strings[1..], true),
+            "unpack" => return unpack(&arg_strings[1..]),
+            "list" => return list(&arg_strings[1..]),
+            "compact" => return compact(&arg_strings[1..]),
+            "extract" => return extract(&arg_strings[1..]),
+            "edit" => return edit(&arg_strings[1..]),
+            "anonymize" => return anonymize_command(&arg_strings[1..]),
+            "organize" => return organize(&arg_strings[1..]),
+            "dedupe" => return dedupe(&arg_strings[1..]),
+            "trim" => return trim_command(&arg_strings[1..]),
+            "export" => return export(&arg_strings[1..]),
+            "dump" => return dump(&arg_strings[1..]),
+            _ => (),
+        }
+    }
+
     // last arg is path
     let input_path = unwrap_option!(arg_strings.pop());
 
@@ -57,8 +187,35 @@ fn main() {
             "-x" | "--compress" => options.compress = Some(true),
             "-d" | "--decompress" => options.compress = Some(false),
             "-r" | "--recursive" => options.recursive = true,
+            "-P" | "--no-follow" => options.follow_symlinks = false,
+            "--one-file-system" => options.one_filesystem = true,
+            "--skip-hidden" => options.skip_hidden = true,
+            "--sniff" => options.sniff = true,
+            "-j" | "--jobs" => {
+                i += 1;
+                options.workers = unwrap_option!(arg_strings.get(i).and_then(|n| n.parse().ok()));
+                #[cfg(not(feature = "zstdmt"))]
+                eprintln!("slpz was built without the zstdmt feature, so -j is ignored");
+            }
             "-k" | "--keep" => options.keep = true,
             "--rm" => options.keep = false,
+            "--include" => {
+                i += 1;
+                include.push(&*unwrap_option!(arg_strings.get(i)).clone().leak());
+            }
+            "--exclude" => {
+                i += 1;
+                exclude.push(&*unwrap_option!(arg_strings.get(i)).clone().leak());
+            }
+            "--older-than" => {
+                i += 1;
+                options.older_than = Some(unwrap_option!(arg_strings.get(i).and_then(|a| parse_age(a))));
+            }
+            "--min-size" => {
+                i += 1;
+                options.min_size = Some(unwrap_option!(arg_strings.get(i).and_then(|s| parse_size(s))));
+            }
+            "--no-preserve" => options.preserve = false,
             "-q" | "--quiet" => options.log = false,
             "-h" | "--help" => {
                 println!("{}", HELP);
@@ -68,11 +225,17 @@ fn main() {
                 println!("slpz version {} - created by Alex Harrison (Aitch)", VERSION);
                 std::process::exit(0);
             }
+            a if a.starts_with("--existing=") => {
+                options.existing = unwrap_option!(Existing::from_arg(&a["--existing=".len()..]));
+            }
             a => eprintln!("unknown argument '{}'", a),
         }
 
         i += 1;
     }
+    // options are Copy, so the patterns live until slpz exits
+    options.include = include.leak();
+    options.exclude = exclude.leak();
 
     if let Err(e) = target_path(&options, std::path::Path::new(&input_path), None) {
         match e {
@@ -83,3 +246,1039 @@ fn main() {
         }
     }
 }
+
+/// Parses ages like '30d'. Suffixes are s, m, h, d and w.
+fn parse_age(age: &str) -> Option<std::time::Duration> {
+    let unit = age.chars().last()?;
+    let n = &age[..age.len() - unit.len_utf8()];
+    let secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 60 * 60 * 24,
+        'w' => 60 * 60 * 24 * 7,
+        _ => return None,
+    };
+    Some(std::time::Duration::from_secs(n.parse::<u64>().ok()?.checked_mul(secs)?))
+}
+
+/// Parses sizes like '500k'. Suffixes are k, M and G, in powers of 1024. No suffix is bytes.
+fn parse_size(size: &str) -> Option<u64> {
+    let (n, mul) = match size.as_bytes().last()? {
+        b'k' | b'K' => (&size[..size.len()-1], 1 << 10),
+        b'M' => (&size[..size.len()-1], 1 << 20),
+        b'G' => (&size[..size.len()-1], 1 << 30),
+        _ => (size, 1),
+    };
+    n.parse::<u64>().ok()?.checked_mul(mul)
+}
+
+fn usage() -> ! {
+    eprintln!("{}", HELP);
+    std::process::exit(1);
+}
+
+macro_rules! unwrap_result {
+    ($e:expr) => {
+        match $e {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn pack(args: &[String], append: bool) {
+    let mut options = Options::DEFAULT;
+    options.compress = Some(true);
+    let mut dictionary = false;
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+
+    let (input_path, args) = unwrap_option!(args.split_last());
+    let (archive_path, args) = unwrap_option!(args.split_last());
+
+    let mut i = 0;
+    while let Some(a) = args.get(i) {
+        match a.as_ref() {
+            "--fast" => options.level = 3,
+            "--small" => options.level = 12,
+            "-r" | "--recursive" => options.recursive = true,
+            "-P" | "--no-follow" => options.follow_symlinks = false,
+            "--skip-hidden" => options.skip_hidden = true,
+            "-q" | "--quiet" => options.log = false,
+            "--dict" if !append => dictionary = true,
+            "--include" => {
+                i += 1;
+                include.push(&*unwrap_option!(args.get(i)).clone().leak());
+            }
+            "--exclude" => {
+                i += 1;
+                exclude.push(&*unwrap_option!(args.get(i)).clone().leak());
+            }
+            "--older-than" => {
+                i += 1;
+                options.older_than = Some(unwrap_option!(args.get(i).and_then(|a| parse_age(a))));
+            }
+            "--min-size" => {
+                i += 1;
+                options.min_size = Some(unwrap_option!(args.get(i).and_then(|s| parse_size(s))));
+            }
+            a => eprintln!("unknown argument '{}'", a),
+        }
+        i += 1;
+    }
+    options.include = include.leak();
+    options.exclude = exclude.leak();
+
+    let input_path = std::path::Path::new(input_path);
+    let mut targets = unwrap_result!(find_targets(&options, input_path)).files;
+    targets.sort();
+
+    let dictionary = match dictionary {
+        false => None,
+        true => {
+            // an evenly spread sample is plenty. replays are read one at a time while training
+            let step = targets.len().div_ceil(256).max(1);
+            let samples = targets.iter()
+                .step_by(step)
+                .filter_map(|t| std::fs::read(t).ok());
+            Some(unwrap_result!(train_dictionary(samples, DICTIONARY_SIZE)))
+        }
+    };
+
+    let archive_path = std::path::Path::new(archive_path);
+    let mut writer = match append {
+        false => unwrap_result!(ArchiveWriter::create(archive_path, options.level, dictionary.as_deref())),
+        true => unwrap_result!(ArchiveWriter::append(archive_path, options.level)),
+    };
+    let existing = writer.entries().iter().map(|e| e.name.clone()).collect::<std::collections::HashSet<_>>();
+
+    for t in targets.iter() {
+        let name = archive_name(input_path, t);
+        if existing.contains(&name) {
+            if options.log { println!("skipped {}, already in archive", t.display()) }
+            continue;
+        }
+
+        let res = std::fs::read(t)
+            .map_err(ArchiveError::Io)
+            .and_then(|slp| writer.add(&name, &slp));
+        match res {
+            Ok(()) => if options.log { println!("packed {}", t.display()) },
+            Err(e) => eprintln!("Error packing {}: {}", t.display(), e),
+        }
+    }
+    unwrap_result!(writer.finish());
+}
+
+/// The path of a target relative to the input path, separated with '/'.
+fn archive_name(input_path: &std::path::Path, target: &std::path::Path) -> String {
+    let relative = match target.strip_prefix(input_path) {
+        Ok(r) if r.as_os_str().is_empty() => std::path::Path::new(target.file_name().unwrap_or_default()),
+        Ok(r) => r,
+        Err(_) => target,
+    };
+    relative.components()
+        .filter(|c| matches!(c, std::path::Component::Normal(_)))
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn unpack(args: &[String]) {
+    let [archive_path, output_dir] = args else { usage() };
+    let archive = unwrap_result!(Archive::open(std::path::Path::new(archive_path)));
+    let mut decompressor = unwrap_option!(archive.decompressor());
+
+    for (i, entry) in archive.entries().iter().enumerate() {
+        let out = std::path::Path::new(output_dir).join(&entry.name);
+        let res = archive.extract(&mut decompressor, i).and_then(|slp| {
+            if let Some(parent) = out.parent() { std::fs::create_dir_all(parent)?; }
+            Ok(std::fs::write(&out, slp)?)
+        });
+        match res {
+            Ok(()) => println!("unpacked {}", out.display()),
+            Err(e) => eprintln!("Error unpacking {}: {}", entry.name, e),
+        }
+    }
+}
+
+fn list(args: &[String]) {
+    let [archive_path] = args else { usage() };
+    let archive = unwrap_result!(Archive::open(std::path::Path::new(archive_path)));
+    for entry in archive.entries() {
+        println!("{:>10}  {}", entry.decompressed_size, entry.name);
+    }
+}
+
+fn compact(args: &[String]) {
+    let [archive_path] = args else { usage() };
+    let archive_path = std::path::Path::new(archive_path);
+    let old_size = unwrap_result!(std::fs::metadata(archive_path)).len();
+    unwrap_result!(compact_archive(archive_path));
+    let new_size = unwrap_result!(std::fs::metadata(archive_path)).len();
+    println!("compacted {} ({} -> {} bytes)", archive_path.display(), old_size, new_size);
+}
+
+fn extract(args: &[String]) {
+    let (output_path, args) = match args {
+        [o, path, rest @ ..] if o == "-o" => (Some(path), rest),
+        _ => (None, args),
+    };
+    let [archive_path, name] = args else { usage() };
+
+    let archive = unwrap_result!(Archive::open(std::path::Path::new(archive_path)));
+    let Some(i) = archive.find(name) else {
+        eprintln!("Error: '{}' is not in the archive", name);
+        std::process::exit(1);
+    };
+    let mut decompressor = unwrap_option!(archive.decompressor());
+    let slp = unwrap_result!(archive.extract(&mut decompressor, i));
+
+    let out = match output_path {
+        Some(o) => std::path::PathBuf::from(o),
+        None => std::path::PathBuf::from(name.rsplit('/').next().unwrap_or(name)),
+    };
+    unwrap_result!(std::fs::write(&out, slp));
+    println!("extracted {}", out.display());
+}
+
+enum Edit {
+    Set(String, ubjson::Value),
+    Remove(String),
+    Name(usize, String),
+}
+
+fn edit(args: &[String]) {
+    let (input_path, args) = unwrap_option!(args.split_last());
+    let mut recursive = false;
+    let mut output_path = None;
+    let mut edits = Vec::new();
+
+    let mut i = 0;
+    while let Some(a) = args.get(i) {
+        // the value after an option
+        let mut value = || {
+            i += 1;
+            unwrap_option!(args.get(i))
+        };
+
+        match a.as_ref() {
+            "-r" | "--recursive" => recursive = true,
+            "-o" => output_path = Some(value()),
+            "--set" => {
+                let (key, v) = unwrap_option!(value().split_once('='));
+                edits.push(Edit::Set(key.to_string(), ubjson::Value::String(v.to_string())));
+            }
+            "--set-int" => {
+                let (key, n) = unwrap_option!(value().split_once('='));
+                let n = unwrap_option!(n.parse().ok());
+                edits.push(Edit::Set(key.to_string(), ubjson::Value::int(n)));
+            }
+            "--remove" => edits.push(Edit::Remove(value().clone())),
+            "--start-at" => {
+                let date = value().clone();
+                edits.push(Edit::Set("startAt".to_string(), ubjson::Value::String(date)));
+            }
+            "--name" => {
+                let (port, name) = unwrap_option!(value().split_once('='));
+                let port = unwrap_option!(port.parse::<usize>().ok().filter(|p| (1..=4).contains(p)));
+                edits.push(Edit::Name(port - 1, name.to_string()));
+            }
+            a => {
+                eprintln!("unknown argument '{}'", a);
+                usage()
+            }
+        }
+        i += 1;
+    }
+
+    rewrite_files(input_path, recursive, output_path, "edit", |file| edit_file(file, &edits));
+}
+
+fn edit_file(file: &[u8], edits: &[Edit]) -> Result<Vec<u8>, String> {
+    let mut error = None;
+    let edited = edit_sections(file, |game_start, metadata| {
+        for edit in edits {
+            match edit {
+                Edit::Set(key, value) => if !metadata.insert_path(key, value.clone()) { 
+                    error = Some(format!("'{}' is not in an object", key)) 
+                },
+                Edit::Remove(key) => { metadata.remove_path(key); }
+                Edit::Name(port, name) => {
+                    if !game_start::set_display_name(game_start, *port, name) {
+                        error = Some(format!("cannot set '{}' in Game Start", name));
+                    }
+                    let key = format!("players.{}.names.netplay", port);
+                    metadata.insert_path(&key, ubjson::Value::String(name.clone()));
+                }
+            }
+        }
+    }).map_err(|e| e.to_string())?;
+
+    match error {
+        Some(e) => Err(e),
+        None => Ok(edited),
+    }
+}
+
+fn anonymize_command(args: &[String]) {
+    let (input_path, args) = unwrap_option!(args.split_last());
+    let mut recursive = false;
+    let mut output_path = None;
+    let mut salt = "";
+
+    let mut i = 0;
+    while let Some(a) = args.get(i) {
+        match a.as_ref() {
+            "-r" | "--recursive" => recursive = true,
+            "-o" => {
+                i += 1;
+                output_path = Some(unwrap_option!(args.get(i)));
+            }
+            "--salt" => {
+                i += 1;
+                salt = unwrap_option!(args.get(i));
+            }
+            a => eprintln!("unknown argument '{}'", a),
+        }
+        i += 1;
+    }
+
+    rewrite_files(input_path, recursive, output_path, "anonymize", |file| {
+        anonymize(file, salt.as_bytes()).map_err(|e| e.to_string())
+    });
+}
+
+/// Rewrites every slp and slpz file in the input path in place, or a single file to `output_path`.
+fn rewrite_files(
+    input_path: &str,
+    recursive: bool,
+    output_path: Option<&String>,
+    verb: &str,
+    rewrite: impl Fn(&[u8]) -> Result<Vec<u8>, String>,
+) {
+    let input_path = std::path::Path::new(input_path);
+    if input_path.is_dir() && output_path.is_some() { usage() }
+
+    for t in replay_files(input_path, recursive).iter() {
+        let out = output_path.map_or(t.clone(), std::path::PathBuf::from);
+        let res = std::fs::read(t)
+            .map_err(|e| e.to_string())
+            .and_then(|file| rewrite(&file))
+            .and_then(|rewritten| {
+                // write next to the output first, so a failed write does not lose the original
+                let mut tmp = out.as_os_str().to_owned();
+                tmp.push(".tmp");
+                std::fs::write(&tmp, &rewritten).map_err(|e| e.to_string())?;
+                std::fs::rename(&tmp, &out).map_err(|e| e.to_string())
+            });
+        match res {
+            Ok(()) => println!("{}ed {}", verb.trim_end_matches('e'), out.display()),
+            Err(e) => eprintln!("Error {}ing {}: {}", verb.trim_end_matches('e'), t.display(), e),
+        }
+    }
+}
+
+/// Finds every slp and slpz file in the input path, or the input path itself if it is a file.
+fn replay_files(input_path: &std::path::Path, recursive: bool) -> Vec<std::path::PathBuf> {
+    let mut options = Options::DEFAULT;
+    options.recursive = recursive;
+    replay_targets(options, input_path).files
+}
+
+/// `replay_files` with the directory filters of `options`. `options.compress` is ignored.
+fn replay_targets(mut options: Options, input_path: &std::path::Path) -> Targets {
+    if !input_path.is_dir() {
+        return Targets { files: vec![input_path.to_path_buf()], unreadable: Vec::new(), will_compress: false }
+    }
+
+    options.compress = Some(true);
+    let mut targets = unwrap_result!(find_targets(&options, input_path));
+    options.compress = Some(false);
+    // the same directories are unreadable in both searches
+    targets.files.extend(unwrap_result!(find_targets(&options, input_path)).files);
+    targets
+}
+
+const TEMPLATE_FIELDS: [&str; 16] = [
+    "date", "time", "stage", "name",
+    "p1_code", "p1_name", "p1_char",
+    "p2_code", "p2_name", "p2_char",
+    "p3_code", "p3_name", "p3_char",
+    "p4_code", "p4_name", "p4_char",
+];
+
+fn organize(args: &[String]) {
+    let (input_path, args) = unwrap_option!(args.split_last());
+    let mut options = Options::DEFAULT;
+    let mut include = Vec::new();
+    let mut exclude = Vec::new();
+    // collisions are numbered without a policy
+    let mut existing = None;
+    let mut dry_run = false;
+    let mut output_dir = None;
+    let mut template = None;
+
+    let mut i = 0;
+    while let Some(a) = args.get(i) {
+        match a.as_ref() {
+            "-r" | "--recursive" => options.recursive = true,
+            "-P" | "--no-follow" => options.follow_symlinks = false,
+            "--skip-hidden" => options.skip_hidden = true,
+            "-q" | "--quiet" => options.log = false,
+            "--include" => {
+                i += 1;
+                include.push(&*unwrap_option!(args.get(i)).clone().leak());
+            }
+            "--exclude" => {
+                i += 1;
+                exclude.push(&*unwrap_option!(args.get(i)).clone().leak());
+            }
+            "--older-than" => {
+                i += 1;
+                options.older_than = Some(unwrap_option!(args.get(i).and_then(|a| parse_age(a))));
+            }
+            "--min-size" => {
+                i += 1;
+                options.min_size = Some(unwrap_option!(args.get(i).and_then(|s| parse_size(s))));
+            }
+            a if a.starts_with("--existing=") => {
+                existing = Some(unwrap_option!(Existing::from_arg(&a["--existing=".len()..])));
+            }
+            "-n" | "--dry-run" => dry_run = true,
+            "-o" => {
+                i += 1;
+                output_dir = Some(unwrap_option!(args.get(i)));
+            }
+            "--template" => {
+                i += 1;
+                template = Some(unwrap_option!(args.get(i)));
+            }
+            a => eprintln!("unknown argument '{}'", a),
+        }
+        i += 1;
+    }
+    options.include = include.leak();
+    options.exclude = exclude.leak();
+
+    let template = unwrap_option!(template);
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else { break };
+        let field = &rest[start+1..start+len];
+        if !TEMPLATE_FIELDS.contains(&field) {
+            eprintln!("Error: unknown template field '{{{}}}'", field);
+            std::process::exit(1);
+        }
+        rest = &rest[start+len..];
+    }
+
+    let input_path = std::path::Path::new(input_path);
+    let root = match output_dir {
+        Some(dir) => std::path::Path::new(dir),
+        None if input_path.is_dir() => input_path,
+        None => input_path.parent().unwrap_or(std::path::Path::new("")),
+    };
+
+    // destinations in this run, which do not exist yet in a dry run
+    let mut taken = std::collections::HashSet::new();
+    let targets = replay_targets(options, input_path);
+    let cancel = std::sync::atomic::AtomicBool::new(false);
+    run_targets(&options, "organize", targets, &(), &cancel, |t| {
+        let file = read_sections(t).map_err(TargetError::Read)?;
+        let name = organized_name(template, &file, t).ok_or(TargetError::InvalidFile)?;
+        let mut out = root.join(&name);
+        // already organized
+        if out == t { return Ok(Outcome::Skipped(out)) }
+
+        match existing {
+            // collisions get numbered, as in 'name_2.slpz'
+            None => {
+                let mut n = 1;
+                while taken.contains(&out) || out.exists() {
+                    n += 1;
+                    let (stem, ext) = name.rsplit_once('.').unwrap();
+                    out = root.join(format!("{}_{}.{}", stem, n, ext));
+                    if out == t { return Ok(Outcome::Skipped(out)) }
+                }
+            }
+            Some(existing) => {
+                let write = match dry_run && taken.contains(&out) && !out.exists() {
+                    true => match existing {
+                        Existing::Skip => false,
+                        Existing::Error => return Err(TargetError::OutputExists),
+                        Existing::Overwrite | Existing::IfNewer => true,
+                    },
+                    false => should_write(existing, t, &out)?,
+                };
+                if !write { return Ok(Outcome::Skipped(out)) }
+            }
+        }
+        taken.insert(out.clone());
+
+        if !dry_run { move_file(t, &out).map_err(TargetError::Write)? }
+        Ok(Outcome::Moved(out))
+    });
+}
+
+/// Reads an slp file, or an slpz file up to its compressed events, enough for `sections`.
+fn read_sections(path: &std::path::Path) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut file = std::fs::File::open(path)?;
+    let mut start = Vec::new();
+    (&mut file).take(25).read_to_end(&mut start)?;
+    if detect_format(&start) == Format::Slpz {
+        let compressed_events_offset = u32::from_be_bytes(start[16..20].try_into().unwrap()) as u64;
+        (&mut file).take(compressed_events_offset.saturating_sub(25)).read_to_end(&mut start)
+    } else {
+        file.read_to_end(&mut start)
+    }?;
+    Ok(start)
+}
+
+/// Fills a template with fields from Game Start and metadata, keeping the file's extension.
+fn organized_name(template: &str, file: &[u8], path: &std::path::Path) -> Option<String> {
+    let sections = sections(file).ok()?;
+    let gs = sections.game_start;
+    let metadata = ubjson::read_metadata(sections.metadata)?;
+    let ext = match detect_format(file) {
+        Format::Slpz => "slpz",
+        _ => "slp",
+    };
+
+    // p1 to p4 are the players in port order, skipping empty ports
+    let ports = (0..4)
+        .filter(|&p| game_start::player_type(gs, p).is_some_and(|t| t != game_start::PLAYER_TYPE_EMPTY))
+        .collect::<Vec<_>>();
+    let start_at = metadata.get("startAt").and_then(|s| s.as_str()).unwrap_or("");
+
+    let field = |field: &str| -> Option<String> {
+        Some(match field {
+            "date" => start_at.get(..10)?.to_string(),
+            "time" => start_at.get(11..19)?.replace(':', ""),
+            "name" => path.file_stem()?.to_string_lossy().into_owned(),
+            "stage" => {
+                let stage = game_start::stage(gs)?;
+                game_start::stage_name(stage).map_or(stage.to_string(), String::from)
+            }
+            _ => {
+                let (player, field) = field.strip_prefix('p')?.split_once('_')?;
+                let port = *ports.get(player.parse::<usize>().ok()? - 1)?;
+                let names = metadata.path(&format!("players.{}.names", port));
+                let metadata_name = |key| names.and_then(|n| n.get(key)).and_then(|n| n.as_str()).map(String::from);
+                match field {
+                    "code" => game_start::connect_code(gs, port)
+                        .filter(|c| !c.is_empty())
+                        .or_else(|| metadata_name("code"))?,
+                    "name" => metadata_name("netplay")
+                        .or_else(|| game_start::display_name(gs, port).map(|n| String::from_utf8_lossy(n).into_owned()))?,
+                    _ => {
+                        let character = game_start::character(gs, port)?;
+                        game_start::character_name(character).map_or(character.to_string(), String::from)
+                    }
+                }
+            }
+        })
+    };
+
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else { break };
+        name.push_str(&rest[..start]);
+
+        let value = field(&rest[start+1..start+len]).filter(|v| !v.is_empty());
+        let value = value.unwrap_or_else(|| "unknown".to_string());
+        // values never add directories or leave the output directory
+        let value = match value.as_str() {
+            "." | ".." => "_".to_string(),
+            _ => value.replace(|c: char| c.is_control() || "/\\:*?\"<>|".contains(c), "_"),
+        };
+        name.push_str(&value);
+
+        rest = &rest[start+len+1..];
+    }
+    name.push_str(rest);
+
+    let name = name.strip_suffix(".slpz").or_else(|| name.strip_suffix(".slp")).unwrap_or(&name);
+    Some(format!("{}.{}", name, ext))
+}
+
+/// Renames a file, creating its directory. Copies it if it cannot be renamed, such as across filesystems.
+fn move_file(from: &std::path::Path, to: &std::path::Path) -> std::io::Result<()> {
+    if let Some(dir) = to.parent() { std::fs::create_dir_all(dir)? }
+    if std::fs::rename(from, to).is_ok() { return Ok(()) }
+    std::fs::copy(from, to)?;
+    std::fs::remove_file(from)
+}
+
+fn dedupe(args: &[String]) {
+    let (input_path, args) = unwrap_option!(args.split_last());
+    let mut recursive = false;
+    let mut by_events = false;
+    let mut remove = false;
+    let mut hardlink = false;
+
+    for a in args {
+        match a.as_ref() {
+            "-r" | "--recursive" => recursive = true,
+            "--events" => by_events = true,
+            "--remove" => remove = true,
+            "--hardlink" => hardlink = true,
+            a => eprintln!("unknown argument '{}'", a),
+        }
+    }
+    if remove && hardlink { usage() }
+
+    let mut decompressor = unwrap_option!(Decompressor::new());
+    let mut games: Vec<Vec<std::path::PathBuf>> = Vec::new();
+    let mut fingerprints = std::collections::HashMap::new();
+    for t in replay_files(std::path::Path::new(input_path), recursive) {
+        let fingerprint = match by_events {
+            false => read_sections(&t).map_err(|e| e.to_string()).and_then(|file| game_start_fingerprint(&file).ok_or("invalid file".into())),
+            true => std::fs::read(&t).map_err(|e| e.to_string()).and_then(|file| events_fingerprint(&mut decompressor, &file)),
+        };
+        match fingerprint {
+            Ok(fingerprint) => {
+                let i = *fingerprints.entry(fingerprint).or_insert_with(|| {
+                    games.push(Vec::new());
+                    games.len() - 1
+                });
+                games[i].push(t);
+            }
+            Err(e) => eprintln!("Error reading {}: {}", t.display(), e),
+        }
+    }
+
+    let mut duplicates = 0;
+    for files in games.iter().filter(|files| files.len() > 1) {
+        let (first, rest) = files.split_first().unwrap();
+        println!("{}", first.display());
+
+        for (i, dup) in rest.iter().enumerate() {
+            duplicates += 1;
+            let res = if remove && by_events {
+                remove_same_events(&mut decompressor, first, dup)
+            } else if remove {
+                std::fs::remove_file(dup).map(|_| "removed").map_err(|e| e.to_string())
+            } else if hardlink {
+                link_duplicate(&files[..=i], dup).map_err(|e| e.to_string())
+            } else {
+                Ok("duplicate")
+            };
+            match res {
+                Ok(action) => println!("  {} {}", action, dup.display()),
+                Err(e) => eprintln!("  Error with {}: {}", dup.display(), e),
+            }
+        }
+    }
+    println!("{} duplicates found", duplicates);
+}
+
+/// Online games are identified by match ID, game number and tiebreaker,
+/// others by Game Start without player names, which includes the random seed.
+fn game_start_fingerprint(file: &[u8]) -> Option<Vec<u8>> {
+    let mut game_start = sections(file).ok()?.game_start.to_vec();
+
+    if let Some(id) = game_start::match_id(&game_start).filter(|id| !id.is_empty()) {
+        let mut fingerprint = id.to_vec();
+        fingerprint.extend_from_slice(&game_start::game_number(&game_start)?.to_be_bytes());
+        fingerprint.extend_from_slice(&game_start::tiebreaker(&game_start)?.to_be_bytes());
+        return Some(fingerprint)
+    }
+
+    for port in 0..4 { game_start::clear_player_names(&mut game_start, port) }
+    Some(game_start)
+}
+
+/// Hashes the slp file without its metadata, so slp and slpz files of a game match.
+fn events_fingerprint(decompressor: &mut Decompressor, file: &[u8]) -> Result<Vec<u8>, String> {
+    use std::hash::{Hash, Hasher};
+
+    let slp = without_metadata(decompressor, file)?;
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    slp.hash(&mut hasher);
+    let mut fingerprint = (slp.len() as u64).to_be_bytes().to_vec();
+    fingerprint.extend_from_slice(&hasher.finish().to_be_bytes());
+    Ok(fingerprint)
+}
+
+/// The slp file without its metadata. slpz files are decompressed.
+fn without_metadata(decompressor: &mut Decompressor, file: &[u8]) -> Result<Vec<u8>, String> {
+    let metadata_size = sections(file).map_err(|e| e.to_string())?.metadata.len();
+    let mut slp = match detect_format(file) {
+        Format::Slpz => decompress(decompressor, file).map_err(|e| e.to_string())?,
+        _ => file.to_vec(),
+    };
+    slp.truncate(slp.len() - metadata_size);
+    Ok(slp)
+}
+
+/// Removes a duplicate found by its events fingerprint, 
+/// if its events are the same as the kept file's and not just their hash.
+fn remove_same_events(
+    decompressor: &mut Decompressor, 
+    kept: &std::path::Path, 
+    dup: &std::path::Path,
+) -> Result<&'static str, String> {
+    let mut events = |path| std::fs::read(path).map_err(|e| e.to_string())
+        .and_then(|file| without_metadata(decompressor, &file));
+    if events(kept)? != events(dup)? { return Ok("not identical, kept") }
+
+    std::fs::remove_file(dup).map_err(|e| e.to_string())?;
+    Ok("removed")
+}
+
+/// Replaces a duplicate with a hard link to an earlier file of the game with the same contents.
+fn link_duplicate(earlier: &[std::path::PathBuf], dup: &std::path::Path) -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let id = |path| std::fs::metadata(path).map(|m| (m.dev(), m.ino()));
+        let dup_id = id(dup)?;
+        for original in earlier {
+            if id(original)? == dup_id { return Ok("already linked") }
+        }
+    }
+
+    let contents = std::fs::read(dup)?;
+    for original in earlier {
+        if std::fs::read(original)? != contents { continue }
+
+        // link next to the duplicate first, so a failed link does not lose it
+        let mut tmp = dup.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::hard_link(original, &tmp)?;
+        std::fs::rename(&tmp, dup)?;
+        return Ok("linked")
+    }
+    Ok("not identical, kept")
+}
+
+fn trim_command(args: &[String]) {
+    let (input_path, args) = unwrap_option!(args.split_last());
+    let mut from_frame = i32::MIN;
+    let mut to_frame = i32::MAX;
+    let mut output_path = None;
+    let mut level = Options::DEFAULT.level;
+
+    let mut i = 0;
+    while let Some(a) = args.get(i) {
+        match a.as_ref() {
+            "--from-frame" => {
+                i += 1;
+                from_frame = unwrap_option!(args.get(i).and_then(|n| n.parse().ok()));
+            }
+            "--to-frame" => {
+                i += 1;
+                to_frame = unwrap_option!(args.get(i).and_then(|n| n.parse().ok()));
+            }
+            "-o" => {
+                i += 1;
+                output_path = Some(std::path::PathBuf::from(unwrap_option!(args.get(i))));
+            }
+            "--fast" => level = 3,
+            "--small" => level = 12,
+            a => eprintln!("unknown argument '{}'", a),
+        }
+        i += 1;
+    }
+
+    let input_path = std::path::Path::new(input_path);
+    let file = unwrap_result!(std::fs::read(input_path));
+    let format = detect_format(&file);
+    let slp = match format {
+        Format::Slp => file,
+        Format::Slpz => {
+            let mut decompressor = unwrap_option!(Decompressor::new());
+            unwrap_result!(decompress(&mut decompressor, &file))
+        }
+        Format::Unknown => unwrap_result!(Err("input file is not an slp or slpz file")),
+    };
+
+    let mut trimmed = unwrap_result!(trim(&slp, from_frame, to_frame));
+    if format == Format::Slpz {
+        let mut compressor = unwrap_option!(Compressor::new(level));
+        trimmed = unwrap_result!(compress(&mut compressor, &trimmed));
+    }
+
+    let output_path = output_path.unwrap_or_else(|| {
+        let mut name = input_path.file_stem().unwrap_or_default().to_owned();
+        name.push("_trimmed.");
+        name.push(if format == Format::Slpz { "slpz" } else { "slp" });
+        input_path.with_file_name(name)
+    });
+    unwrap_result!(std::fs::write(&output_path, &trimmed));
+    println!("trimmed {}", output_path.display());
+}
+
+#[cfg(feature = "parquet")]
+fn export(args: &[String]) {
+    let (input_path, args) = unwrap_option!(args.split_last());
+    let mut recursive = false;
+    let mut output_dir = None;
+
+    let mut i = 0;
+    while let Some(a) = args.get(i) {
+        match a.as_ref() {
+            "-r" | "--recursive" => recursive = true,
+            "-o" => {
+                i += 1;
+                output_dir = Some(unwrap_option!(args.get(i)));
+            }
+            "--format" => {
+                i += 1;
+                if unwrap_option!(args.get(i)) != "parquet" { usage() }
+            }
+            a => eprintln!("unknown argument '{}'", a),
+        }
+        i += 1;
+    }
+
+    let output_dir = std::path::Path::new(unwrap_option!(output_dir));
+    let input_path = std::path::Path::new(input_path);
+    let mut exporter = unwrap_result!(ParquetExporter::create(output_dir));
+    let mut decompressor = unwrap_option!(Decompressor::new());
+    for t in replay_files(input_path, recursive) {
+        let name = archive_name(input_path, &t);
+        let res = std::fs::read(&t)
+            .map_err(ExportError::Io)
+            .and_then(|file| exporter.add(&mut decompressor, &name, &file));
+        match res {
+            Ok(()) => println!("exported {}", t.display()),
+            Err(e) => eprintln!("Error exporting {}: {}", t.display(), e),
+        }
+    }
+    unwrap_result!(exporter.finish());
+}
+
+#[cfg(not(feature = "parquet"))]
+fn export(_args: &[String]) {
+    eprintln!("Error: slpz was built without the parquet feature");
+    std::process::exit(1);
+}
+
+fn dump(args: &[String]) {
+    let (input_path, args) = unwrap_option!(args.split_last());
+    let mut list_events = false;
+    for a in args {
+        match a.as_ref() {
+            "--json" => (),
+            "--events" => list_events = true,
+            a => eprintln!("unknown argument '{}'", a),
+        }
+    }
+
+    let file = unwrap_result!(std::fs::read(input_path));
+    let mut out = String::from("{\n");
+    let mut field = |key: &str, value: String| {
+        if out.len() > 2 { out.push_str(",\n") }
+        out.push_str("  ");
+        ubjson::write_json_str(key, &mut out);
+        out.push_str(": ");
+        out.push_str(&value);
+    };
+
+    field("file", json_str(input_path));
+    let res = match detect_format(&file) {
+        Format::Slp => {
+            field("format", json_str("slp"));
+            dump_slp(&file, list_events, &mut field)
+        }
+        Format::Slpz => {
+            field("format", json_str("slpz"));
+            dump_slpz(&file, list_events, &mut field)
+        }
+        Format::Unknown => {
+            field("format", json_str("unknown"));
+            Err((0, "not an slp or slpz file".to_string()))
+        }
+    };
+    if let Err((offset, reason)) = &res {
+        field("error", format!("{{\"offset\": {}, \"reason\": {}}}", offset, json_str(reason)));
+    }
+
+    out.push_str("\n}");
+    println!("{}", out);
+    if res.is_err() { std::process::exit(1) }
+}
+
+/// Where parsing stopped and why.
+type DumpError = (usize, String);
+
+fn json_str(s: &str) -> String {
+    let mut out = String::new();
+    ubjson::write_json_str(s, &mut out);
+    out
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn dump_slpz(file: &[u8], list_events: bool, field: &mut impl FnMut(&str, String)) -> Result<(), DumpError> {
+    let word = |i: usize| u32::from_be_bytes(file[i..i+4].try_into().unwrap());
+    field("slpz_header", format!(
+        "{{\"version\": {}, \"event_sizes_offset\": {}, \"game_start_offset\": {}, \"metadata_offset\": {}, \
+        \"compressed_events_offset\": {}, \"decompressed_events_size\": {}}}",
+        word(0), word(4), word(8), word(12), word(16), word(20),
+    ));
+    Header::read(file).map_err(|_| (0, "slpz header offsets are out of order or past the end of the file".to_string()))?;
+
+    // the rest is the slp file the events decompress to
+    let mut decompressor = Decompressor::new().ok_or((0, "zstd failed to initialize".to_string()))?;
+    let slp = decompress(&mut decompressor, file).map_err(|e| (word(16) as usize, format!("events: {}", e)))?;
+    field("decompressed_size", slp.len().to_string());
+    field("note", json_str("offsets below are in the decompressed slp file"));
+    dump_slp(&slp, list_events, field)
+}
+
+fn dump_slp(slp: &[u8], list_events: bool, field: &mut impl FnMut(&str, String)) -> Result<(), DumpError> {
+    let err = |offset: usize, reason: String| Err((offset, reason));
+    if slp.len() < 17 { return err(slp.len(), "file ends before Event Payloads".to_string()) }
+
+    let raw_len = u32::from_be_bytes(slp[11..15].try_into().unwrap()) as usize;
+    field("raw_length", raw_len.to_string());
+    let metadata_offset = 15 + raw_len;
+    if raw_len == 0 { return err(11, "raw length is 0, the replay was not finished".to_string()) }
+    if metadata_offset > slp.len() {
+        return err(11, format!("raw length {} goes past the end of the file at {}", raw_len, slp.len()))
+    }
+
+    // Event Payloads
+    let payloads_size = slp[16] as usize;
+    if payloads_size % 3 != 1 {
+        return err(16, format!("Event Payloads size {} is not 1 + 3 per command", payloads_size))
+    }
+    if 16 + payloads_size > metadata_offset { return err(16, "Event Payloads goes past the raw data".to_string()) }
+    let mut event_sizes = [0u16; 256];
+    let mut table = Vec::new();
+    for entry in slp[17..16 + payloads_size].chunks_exact(3) {
+        let size = u16::from_be_bytes([entry[1], entry[2]]);
+        event_sizes[entry[0] as usize] = size;
+        table.push(format!("\"0x{:02x}\": {}", entry[0], size));
+    }
+    field("event_payloads", format!("{{{}}}", table.join(", ")));
+
+    // Game Start
+    let game_start_offset = 16 + payloads_size;
+    let game_start_size = event_sizes[0x36] as usize;
+    if slp.get(game_start_offset) != Some(&0x36) {
+        return err(game_start_offset, "expected Game Start (0x36) after Event Payloads".to_string())
+    }
+    if game_start_size == 0 { return err(16, "Event Payloads has no size for Game Start".to_string()) }
+    let events_offset = game_start_offset + 1 + game_start_size;
+    if events_offset > metadata_offset { return err(game_start_offset, "Game Start goes past the raw data".to_string()) }
+    field("game_start", dump_game_start(&slp[game_start_offset..events_offset]));
+
+    // events
+    let mut counts = [0u32; 256];
+    let mut events = Vec::new();
+    let mut i = events_offset;
+    let mut res = Ok(());
+    while i < metadata_offset {
+        let command = slp[i];
+        let size = event_sizes[command as usize] as usize;
+        if size == 0 {
+            res = err(i, format!("command 0x{:02x} is not in Event Payloads", command));
+            break
+        }
+        if i + 1 + size > metadata_offset {
+            res = err(i, format!("command 0x{:02x} of size {} goes past the raw data at {}", command, size, metadata_offset));
+            break
+        }
+        counts[command as usize] += 1;
+        if list_events {
+            events.push(format!("{{\"offset\": {}, \"command\": \"0x{:02x}\", \"payload\": \"{}\"}}", i, command, hex(&slp[i+1..][..size])));
+        }
+        i += 1 + size;
+    }
+
+    let counts = (0..256)
+        .filter(|&c| counts[c] > 0)
+        .map(|c| format!("\"0x{:02x}\": {}", c, counts[c]))
+        .collect::<Vec<_>>();
+    field("event_counts", format!("{{{}}}", counts.join(", ")));
+    if list_events { field("events", format!("[\n    {}\n  ]", events.join(",\n    "))) }
+    res?;
+
+    // metadata
+    let metadata = ubjson::read_metadata(&slp[metadata_offset..])
+        .ok_or((metadata_offset, "metadata is not valid UBJSON".to_string()))?;
+    let mut json = String::new();
+    metadata.write_json(&mut json);
+    field("metadata", json);
+    Ok(())
+}
+
+fn dump_game_start(gs: &[u8]) -> String {
+    let mut fields = vec![
+        format!("\"version\": \"{}.{}.{}\"", gs[1], gs.get(2).unwrap_or(&0), gs.get(3).unwrap_or(&0)),
+    ];
+    if let Some(stage) = game_start::stage(gs) {
+        fields.push(format!("\"stage\": {}, \"stage_name\": {}", stage, json_str(game_start::stage_name(stage).unwrap_or(""))));
+    }
+    if let Some(seed) = game_start::random_seed(gs) { fields.push(format!("\"random_seed\": {}", seed)) }
+    if let Some(id) = game_start::match_id(gs) {
+        fields.push(format!("\"match_id\": {}", json_str(&String::from_utf8_lossy(id))));
+    }
+    if let Some(n) = game_start::game_number(gs) { fields.push(format!("\"game_number\": {}", n)) }
+    if let Some(n) = game_start::tiebreaker(gs) { fields.push(format!("\"tiebreaker\": {}", n)) }
+
+    let mut players = Vec::new();
+    for port in 0..4 {
+        let mut player = vec![format!("\"port\": {}", port + 1)];
+        if let Some(t) = game_start::player_type(gs, port) { player.push(format!("\"type\": {}", t)) }
+        if let Some(c) = game_start::character(gs, port) {
+            player.push(format!("\"character\": {}, \"character_name\": {}", c, json_str(game_start::character_name(c).unwrap_or(""))));
+        }
+        if let Some(name) = game_start::display_name(gs, port) {
+            player.push(format!("\"display_name\": {}", json_str(&String::from_utf8_lossy(name))));
+        }
+        if let Some(code) = game_start::connect_code(gs, port) {
+            player.push(format!("\"connect_code\": {}", json_str(&code)));
+        }
+        players.push(format!("{{{}}}", player.join(", ")));
+    }
+    fields.push(format!("\"players\": [{}]", players.join(", ")));
+    fields.push(format!("\"payload\": \"{}\"", hex(&gs[1..])));
+    format!("{{{}}}", fields.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn age() {
+        assert_eq!(parse_age("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_age("5m"), Some(Duration::from_secs(5 * 60)));
+        assert_eq!(parse_age("12h"), Some(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_age("30d"), Some(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(parse_age("2w"), Some(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert_eq!(parse_age("0d"), Some(Duration::ZERO));
+
+        for age in ["", "d", "30", "30x", "-1d", "1.5d", "5µ", "µ", "99999999999999999999w"] {
+            assert_eq!(parse_age(age), None, "{}", age);
+        }
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("100"), Some(100));
+        assert_eq!(parse_size("500k"), Some(500 << 10));
+        assert_eq!(parse_size("500K"), Some(500 << 10));
+        assert_eq!(parse_size("3M"), Some(3 << 20));
+        assert_eq!(parse_size("2G"), Some(2 << 30));
+
+        for size in ["", "k", "1.5M", "-1", "3m", "1T", "99999999999999G"] {
+            assert_eq!(parse_size(size), None, "{}", size);
+        }
+    }
+}
@@ -0,0 +1,828 @@
+//! Compression and decompression of files and directories. Used by the slpz program.
+
+use crate::{Compressor, Decompressor, CompError, DecompError, Format};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TargetPathError {
+    PathNotFound,
+    PathInvalid,
+    CompressOrDecompressAmbiguous,
+    ZstdInitError,
+}
+
+impl std::fmt::Display for TargetPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            TargetPathError::PathNotFound => "Replay path not found",
+            TargetPathError::PathInvalid => "Replay path invalid",
+            TargetPathError::CompressOrDecompressAmbiguous => "Not a slp or slpz file",
+            TargetPathError::ZstdInitError => "Failed to init zstd",
+        })
+    }
+}
+
+/// Why compressing or decompressing a single file failed.
+#[derive(Debug)]
+pub enum TargetError {
+    Read(std::io::Error),
+    Write(std::io::Error),
+    Remove(std::io::Error),
+    Compress(CompError),
+    Decompress(DecompError),
+    InvalidFilename,
+    /// The output file exists and `Options::existing` is `Existing::Error`.
+    OutputExists,
+    ZstdInitError,
+    /// The file was not finished because the batch was cancelled.
+    Cancelled,
+    /// The file is not a valid slp or slpz file.
+    InvalidFile,
+}
+
+impl std::fmt::Display for TargetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetError::Read(e) => write!(f, "Failed to read file: {}", e),
+            TargetError::Write(e) => write!(f, "Failed to write file: {}", e),
+            TargetError::Remove(e) => write!(f, "Failed to remove file: {}", e),
+            TargetError::Compress(e) => write!(f, "{}", e),
+            TargetError::Decompress(e) => write!(f, "{}", e),
+            TargetError::InvalidFilename => write!(f, "Failed to create new filename"),
+            TargetError::OutputExists => write!(f, "Output file already exists"),
+            TargetError::ZstdInitError => write!(f, "Failed to init zstd"),
+            TargetError::Cancelled => write!(f, "Cancelled"),
+            TargetError::InvalidFile => write!(f, "Not a valid slp or slpz file"),
+        }
+    }
+}
+
+/// What to do when the output file already exists.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Existing {
+    /// Replace the output file.
+    Overwrite,
+    /// Leave the output file and skip the target.
+    Skip,
+    /// Fail the target with `TargetError::OutputExists`.
+    Error,
+    /// Replace the output file only if the target was modified more recently.
+    IfNewer,
+}
+
+impl Existing {
+    /// Parses the values of the `--existing` option.
+    pub fn from_arg(arg: &str) -> Option<Existing> {
+        match arg {
+            "overwrite" => Some(Existing::Overwrite),
+            "skip" => Some(Existing::Skip),
+            "error" => Some(Existing::Error),
+            "if-newer" => Some(Existing::IfNewer),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Options {
+    pub keep: bool,
+    pub compress: Option<bool>,
+    pub recursive: bool,
+    pub threading: bool,
+    /// must be between 1 and 19.
+    pub level: i32,
+    /// zstd worker threads used per file. 0 compresses on the calling thread.
+    /// Ignored without the zstdmt feature.
+    ///
+    /// Only used when files are compressed one at a time, 
+    /// as directories are already split between threads.
+    pub workers: u32,
+    /// Targets are skipped without removing them, even if `keep` is false.
+    pub existing: Existing,
+    /// Copy modification time, access time and permissions from targets to the new files.
+    pub preserve: bool,
+    /// Glob patterns. If not empty, only files in directories matching one of these are targeted.
+    /// See `glob_match`.
+    pub include: &'static [&'static str],
+    /// Glob patterns. Files in directories matching one of these are not targeted.
+    pub exclude: &'static [&'static str],
+    /// Only target files in directories last modified longer ago than this.
+    pub older_than: Option<std::time::Duration>,
+    /// Only target files in directories at least this many bytes in size.
+    pub min_size: Option<u64>,
+    /// Follow symlinks to files and directories in directories. Symlink cycles are skipped. On by default.
+    pub follow_symlinks: bool,
+    /// Do not recurse into directories on other filesystems. Only supported on unix.
+    pub one_filesystem: bool,
+    /// Skip files and directories in directories whose names start with '.'.
+    pub skip_hidden: bool,
+    /// Choose files in directories by their contents instead of their extension. See `detect_format`.
+    ///
+    /// Single files are always checked by their contents, unless `compress` is set.
+    pub sniff: bool,
+    pub log: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self { Options::DEFAULT }
+}
+
+impl Options {
+    pub const DEFAULT: Self = Options {
+        keep: true,
+        compress: None,
+        recursive: false,
+        threading: true,
+        level: 3,
+        workers: 0,
+        existing: Existing::Overwrite,
+        preserve: true,
+        include: &[],
+        exclude: &[],
+        older_than: None,
+        min_size: None,
+        follow_symlinks: true,
+        one_filesystem: false,
+        skip_hidden: false,
+        sniff: false,
+        log: true,
+    };
+}
+
+/// What happened to a finished file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    /// The path of the new slpz file.
+    Compressed(std::path::PathBuf),
+    /// The path of the new slp file.
+    Decompressed(std::path::PathBuf),
+    /// The path of the existing output file, which was left alone. See `Options::existing`.
+    Skipped(std::path::PathBuf),
+    /// The new path of a moved file. See `run_targets`.
+    Moved(std::path::PathBuf),
+}
+
+/// Receives progress from `target_path_with_progress` and `run_targets`.
+///
+/// Methods are called from worker threads.
+/// Exactly one of `on_file_done` or `on_error` is called for each file, unless the batch is cancelled.
+pub trait Progress: Sync {
+    /// Called once before any file is started.
+    /// `total` includes directories that could not be read, which are passed to `on_error`.
+    fn on_start(&self, _total: usize, _total_bytes: u64) {}
+
+    /// Called when a file is finished. `bytes` is the size of the original file.
+    fn on_file_done(&self, _path: &std::path::Path, _outcome: &Outcome, _bytes: u64) {}
+
+    /// Called when a file fails.
+    fn on_error(&self, _path: &std::path::Path, _error: &TargetError) {}
+}
+
+/// Ignores progress.
+impl Progress for () {}
+
+/// Sends the number of targets, then '1' for each file finished or failed.
+///
+/// Send errors are ignored, so the receiver may be dropped at any time.
+impl Progress for std::sync::mpsc::Sender<usize> {
+    fn on_start(&self, total: usize, _total_bytes: u64) { let _ = self.send(total); }
+    fn on_file_done(&self, _path: &std::path::Path, _outcome: &Outcome, _bytes: u64) { let _ = self.send(1); }
+    fn on_error(&self, _path: &std::path::Path, _error: &TargetError) { let _ = self.send(1); }
+}
+
+/// Library access to slpz program functionality.
+///
+/// If Some, the sender will first send the number of targets.
+/// After that, the sender will send '1' for each target completed.
+/// See `target_path_with_progress` for more detailed progress and cancellation.
+///
+/// - Threaded directory compression/decompression.
+/// - Compression/decompression autodetection.
+/// - Deletion of old files.
+pub fn target_path(
+    options: &Options,
+    path: &std::path::Path,
+    sender: Option<std::sync::mpsc::Sender<usize>>,
+) -> Result<(), TargetPathError> {
+    let cancel = std::sync::atomic::AtomicBool::new(false);
+    match sender {
+        Some(sender) => target_path_with_progress(options, path, &sender, &cancel),
+        None => target_path_with_progress(options, path, &(), &cancel),
+    }
+}
+
+/// `target_path` with per file progress and cancellation.
+///
+/// Setting `cancel` stops workers from starting new files. 
+/// Files that have already started are finished.
+pub fn target_path_with_progress(
+    options: &Options,
+    path: &std::path::Path,
+    progress: &dyn Progress,
+    cancel: &std::sync::atomic::AtomicBool,
+) -> Result<(), TargetPathError> {
+    let Targets { files: targets, unreadable, will_compress } = find_targets(options, path)?;
+
+    let total_bytes = targets.iter()
+        .filter_map(|t| std::fs::metadata(t).ok())
+        .map(|m| m.len())
+        .sum();
+    progress.on_start(targets.len() + unreadable.len(), total_bytes);
+
+    for (dir, e) in unreadable {
+        eprintln!("Error reading {}: {}", dir.display(), e);
+        progress.on_error(&dir, &TargetError::Read(e));
+    }
+
+    if !options.threading || targets.len() < 8 {
+        let codec = if will_compress {
+            #[cfg(feature = "zstdmt")]
+            let compressor = Compressor::with_workers(options.level, options.workers);
+            #[cfg(not(feature = "zstdmt"))]
+            let compressor = Compressor::new(options.level);
+            compressor.map(Codec::Compress)
+        } else {
+            Decompressor::new().map(Codec::Decompress)
+        };
+        let mut codec = codec.ok_or(TargetPathError::ZstdInitError)?;
+        target_files(&mut codec, options, &targets, progress, cancel);
+    } else {
+        // split into 8 approximately equal slices (why is this so annoying?)
+        let mut slices: [&[std::path::PathBuf]; 8] = [&[]; 8];
+        let chunk = targets.len() / 8;
+        let split = (chunk + 1) * (targets.len() % 8);
+        for (i, c) in targets[..split].chunks(chunk+1).chain(targets[split..].chunks(chunk)).enumerate() {
+            slices[i] = c;
+        }
+
+        std::thread::scope(|scope| {
+            for s in slices {
+                scope.spawn(move || {
+                    let codec = if will_compress {
+                        Compressor::new(options.level).map(Codec::Compress)
+                    } else {
+                        Decompressor::new().map(Codec::Decompress)
+                    };
+                    match codec {
+                        Some(mut codec) => target_files(&mut codec, options, s, progress, cancel),
+                        None => {
+                            eprintln!("Error: Failed to init zstd");
+                            for t in s { progress.on_error(t, &TargetError::ZstdInitError) }
+                        }
+                    }
+                });
+            }
+        })
+    }
+    
+    Ok(())
+}
+
+enum Codec {
+    Compress(Compressor),
+    Decompress(Decompressor),
+}
+
+fn target_files(
+    codec: &mut Codec,
+    options: &Options,
+    targets: &[std::path::PathBuf],
+    progress: &dyn Progress,
+    cancel: &std::sync::atomic::AtomicBool,
+) {
+    let mut buffers = Buffers::default();
+    for t in targets {
+        if cancel.load(std::sync::atomic::Ordering::Relaxed) { return }
+
+        let (verb, res) = match codec {
+            Codec::Compress(c) => ("compress", compress_file(c, options, t, &mut buffers)),
+            Codec::Decompress(d) => ("decompress", decompress_file(d, options, t, &mut buffers)),
+        };
+
+        let bytes = match res {
+            // skipped before reading
+            Ok(Outcome::Skipped(_)) => std::fs::metadata(t).map_or(0, |m| m.len()),
+            _ => buffers.read.len() as u64,
+        };
+        report(options, verb, t, res, bytes, progress);
+    }
+}
+
+/// Runs `run` on each file in `targets` on the calling thread,
+/// with the logging, progress and cancellation of `target_path_with_progress`.
+///
+/// For commands that do something other than compress or decompress the targets, such as moving them.
+/// `verb` names the command in log messages, e.g. "organize".
+pub fn run_targets(
+    options: &Options,
+    verb: &str,
+    targets: Targets,
+    progress: &dyn Progress,
+    cancel: &std::sync::atomic::AtomicBool,
+    mut run: impl FnMut(&std::path::Path) -> Result<Outcome, TargetError>,
+) {
+    let Targets { files, unreadable, .. } = targets;
+
+    // sizes from before the files are moved
+    let sizes = files.iter()
+        .map(|t| std::fs::metadata(t).map_or(0, |m| m.len()))
+        .collect::<Vec<_>>();
+    progress.on_start(files.len() + unreadable.len(), sizes.iter().sum());
+
+    for (dir, e) in unreadable {
+        eprintln!("Error reading {}: {}", dir.display(), e);
+        progress.on_error(&dir, &TargetError::Read(e));
+    }
+
+    for (t, bytes) in files.iter().zip(sizes) {
+        if cancel.load(std::sync::atomic::Ordering::Relaxed) { return }
+        report(options, verb, t, run(t), bytes, progress);
+    }
+}
+
+/// Logs the result of a target and passes it to `progress`.
+fn report(
+    options: &Options,
+    verb: &str,
+    t: &std::path::Path,
+    res: Result<Outcome, TargetError>,
+    bytes: u64,
+    progress: &dyn Progress,
+) {
+    let verb = verb.trim_end_matches('e');
+    match res {
+        Ok(outcome) => {
+            if options.log {
+                match &outcome {
+                    Outcome::Skipped(out) if out == t => println!("skipped {}, already {}ed", t.display(), verb),
+                    Outcome::Skipped(out) => println!("skipped {}, {} exists", t.display(), out.display()),
+                    Outcome::Moved(out) => println!("{} -> {}", t.display(), out.display()),
+                    Outcome::Compressed(_) | Outcome::Decompressed(_) => {
+                        println!("{}ed {}", verb, t.display());
+                        if !options.keep { println!("removed {}", t.display()) }
+                    }
+                }
+            }
+            progress.on_file_done(t, &outcome, bytes);
+        }
+        Err(e) => {
+            eprintln!("Error {}ing {}: {}", verb, t.display(), e);
+            progress.on_error(t, &e);
+        }
+    }
+}
+
+/// The files found by `find_targets`.
+pub struct Targets {
+    pub files: Vec<std::path::PathBuf>,
+    /// Directories, or entries of directories, that could not be read.
+    pub unreadable: Vec<(std::path::PathBuf, std::io::Error)>,
+    pub will_compress: bool,
+}
+
+/// Returns the files `target_path` would target and whether they should be compressed.
+pub fn find_targets(
+    options: &Options,
+    path: &std::path::Path,
+) -> Result<Targets, TargetPathError> {
+    if !matches!(path.try_exists(), Ok(true)) { return Err(TargetPathError::PathNotFound) }
+    
+    let mut targets = Vec::new();
+    let mut unreadable = Vec::new();
+    let mut should_compress = options.compress;
+
+    if path.is_dir() {
+        let c = match should_compress {
+            Some(c) => c,
+            None => return Err(TargetPathError::CompressOrDecompressAmbiguous),
+        };
+        let ex = std::ffi::OsStr::new(if c { "slp" } else { "slpz" });
+        let mut walk = Walk {
+            options,
+            root: path,
+            root_device: std::fs::metadata(path).ok().and_then(|m| device(&m)),
+            ex,
+            format: if c { Format::Slp } else { Format::Slpz },
+            visited: std::collections::HashSet::new(),
+            targets: &mut targets,
+            unreadable: &mut unreadable,
+        };
+        walk.dir(path);
+    } else if path.is_file() {
+        targets.push(path.to_path_buf());
+        if should_compress == None {
+            // contents take priority over misleading extensions
+            match sniff_file(path) {
+                Ok(Format::Slp) => should_compress = Some(true),
+                Ok(Format::Slpz) => should_compress = Some(false),
+                _ => {
+                    let ex = path.extension();
+                    if ex == Some(std::ffi::OsStr::new("slp")) {
+                        should_compress = Some(true);
+                    } else if ex == Some(std::ffi::OsStr::new("slpz")) {
+                        should_compress = Some(false);
+                    }
+                }
+            }
+        }
+    } else {
+        return Err(TargetPathError::PathInvalid);
+    }
+
+    let will_compress = match should_compress {
+        Some(n) => n,
+        None => return Err(TargetPathError::CompressOrDecompressAmbiguous),
+    };
+
+    Ok(Targets { files: targets, unreadable, will_compress })
+}
+
+/// File buffers reused between targets.
+#[derive(Default)]
+pub(crate) struct Buffers {
+    read: Vec<u8>,
+    write: Vec<u8>,
+}
+
+/// Detects the format of a file by reading its start.
+fn sniff_file(path: &std::path::Path) -> std::io::Result<Format> {
+    use std::io::Read;
+    let mut start = Vec::with_capacity(32);
+    std::fs::File::open(path)?.take(32).read_to_end(&mut start)?;
+    Ok(crate::detect_format(&start))
+}
+
+/// Returns the metadata of the file from before it was read.
+fn read_into(path: &std::path::Path, buf: &mut Vec<u8>) -> std::io::Result<std::fs::Metadata> {
+    use std::io::Read;
+    buf.clear();
+    let mut file = std::fs::File::open(path)?;
+    let metadata = file.metadata()?;
+    file.read_to_end(buf)?;
+    Ok(metadata)
+}
+
+/// Writes the output file, copying times and permissions from `source` if given.
+///
+/// The data is written to a temporary file next to `path` and renamed over it,
+/// so an existing output is replaced even if it is read only, and is never left half written.
+fn write_output(path: &std::path::Path, data: &[u8], source: Option<&std::fs::Metadata>) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = std::path::PathBuf::from(tmp);
+
+    let res = write_new(&tmp, data, source).and_then(|()| {
+        // windows does not replace read only files
+        #[cfg(windows)]
+        if let Ok(metadata) = std::fs::metadata(path) {
+            let mut permissions = metadata.permissions();
+            if permissions.readonly() {
+                permissions.set_readonly(false);
+                std::fs::set_permissions(path, permissions)?;
+            }
+        }
+        std::fs::rename(&tmp, path)
+    });
+    if res.is_err() { let _ = std::fs::remove_file(&tmp); }
+    res
+}
+
+fn write_new(path: &std::path::Path, data: &[u8], source: Option<&std::fs::Metadata>) -> std::io::Result<()> {
+    use std::io::Write;
+    // left read only by an interrupted write
+    let _ = std::fs::remove_file(path);
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(data)?;
+
+    if let Some(source) = source {
+        let mut times = std::fs::FileTimes::new();
+        if let Ok(modified) = source.modified() { times = times.set_modified(modified) }
+        if let Ok(accessed) = source.accessed() { times = times.set_accessed(accessed) }
+        file.set_times(times)?;
+        // set last, as the source may be read only
+        file.set_permissions(source.permissions())?;
+    }
+
+    Ok(())
+}
+
+/// Compresses a file, writing the slpz file next to it.
+pub(crate) fn compress_file(
+    c: &mut Compressor, 
+    options: &Options, 
+    t: &std::path::Path, 
+    buffers: &mut Buffers,
+) -> Result<Outcome, TargetError> {
+    let mut out = t.to_path_buf();
+    // files with misleading extensions would be overwritten
+    if !out.set_extension("slpz") || out == t { return Err(TargetError::InvalidFilename) }
+    if !should_write(options.existing, t, &out)? { return Ok(Outcome::Skipped(out)) }
+
+    let metadata = read_into(t, &mut buffers.read).map_err(TargetError::Read)?;
+    c.compress_into(&buffers.read, &mut buffers.write).map_err(TargetError::Compress)?;
+
+    write_output(&out, &buffers.write, options.preserve.then_some(&metadata)).map_err(TargetError::Write)?;
+    if !options.keep { std::fs::remove_file(t).map_err(TargetError::Remove)?; }
+
+    Ok(Outcome::Compressed(out))
+}
+
+/// Decompresses a file, writing the slp file next to it.
+pub(crate) fn decompress_file(
+    d: &mut Decompressor, 
+    options: &Options, 
+    t: &std::path::Path, 
+    buffers: &mut Buffers,
+) -> Result<Outcome, TargetError> {
+    let mut out = t.to_path_buf();
+    // files with misleading extensions would be overwritten
+    if !out.set_extension("slp") || out == t { return Err(TargetError::InvalidFilename) }
+    if !should_write(options.existing, t, &out)? { return Ok(Outcome::Skipped(out)) }
+
+    let metadata = read_into(t, &mut buffers.read).map_err(TargetError::Read)?;
+    d.decompress_into(&buffers.read, &mut buffers.write).map_err(TargetError::Decompress)?;
+
+    write_output(&out, &buffers.write, options.preserve.then_some(&metadata)).map_err(TargetError::Write)?;
+    if !options.keep { std::fs::remove_file(t).map_err(TargetError::Remove)?; }
+
+    Ok(Outcome::Decompressed(out))
+}
+
+/// Applies the `Existing` policy if the output file exists. Returns whether to write the output file.
+pub fn should_write(existing: Existing, t: &std::path::Path, out: &std::path::Path) -> Result<bool, TargetError> {
+    let out_metadata = match std::fs::metadata(out) {
+        Ok(m) => m,
+        Err(_) => return Ok(true),
+    };
+
+    match existing {
+        Existing::Overwrite => Ok(true),
+        Existing::Skip => Ok(false),
+        Existing::Error => Err(TargetError::OutputExists),
+        Existing::IfNewer => {
+            let target_modified = std::fs::metadata(t).and_then(|m| m.modified());
+            match (target_modified, out_metadata.modified()) {
+                (Ok(target_modified), Ok(out_modified)) => Ok(target_modified > out_modified),
+                // can't tell, so assume the output is out of date
+                _ => Ok(true),
+            }
+        }
+    }
+}
+
+/// Directory recursion state for `find_targets`.
+struct Walk<'a> {
+    options: &'a Options,
+    root: &'a std::path::Path,
+    root_device: Option<u64>,
+    ex: &'a std::ffi::OsStr,
+    /// Used instead of `ex` if `Options::sniff` is set.
+    format: Format,
+    /// Canonical paths of directories already walked, to avoid symlink cycles.
+    visited: std::collections::HashSet<std::path::PathBuf>,
+    targets: &'a mut Vec<std::path::PathBuf>,
+    unreadable: &'a mut Vec<(std::path::PathBuf, std::io::Error)>,
+}
+
+impl Walk<'_> {
+    fn dir(&mut self, path: &std::path::Path) {
+        match std::fs::canonicalize(path) {
+            Ok(canonical) => if !self.visited.insert(canonical) { return },
+            Err(e) => return self.unreadable.push((path.to_path_buf(), e)),
+        }
+
+        let entries = match std::fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => return self.unreadable.push((path.to_path_buf(), e)),
+        };
+
+        for f in entries {
+            let f = match f {
+                Ok(f) => f,
+                Err(e) => {
+                    self.unreadable.push((path.to_path_buf(), e));
+                    continue;
+                }
+            };
+
+            if self.options.skip_hidden && f.file_name().as_encoded_bytes().starts_with(b".") { continue }
+
+            let path = f.path();
+            let metadata = match f.file_type() {
+                Ok(t) if t.is_symlink() && !self.options.follow_symlinks => continue,
+                // dangling symlinks are skipped
+                Ok(t) if t.is_symlink() => match std::fs::metadata(&path) {
+                    Ok(m) => m,
+                    Err(_) => continue,
+                },
+                _ => match f.metadata() {
+                    Ok(m) => m,
+                    Err(e) => {
+                        self.unreadable.push((path, e));
+                        continue;
+                    }
+                },
+            };
+
+            if metadata.is_dir() {
+                if !self.options.recursive { continue }
+                if self.options.one_filesystem && device(&metadata) != self.root_device { continue }
+                self.dir(&path);
+            } else if metadata.is_file() && passes_filters(self.options, self.root, &path, &metadata) {
+                let matches = match self.options.sniff {
+                    false => path.extension() == Some(self.ex),
+                    true => match sniff_file(&path) {
+                        Ok(format) => format == self.format,
+                        Err(e) => {
+                            self.unreadable.push((path, e));
+                            continue;
+                        }
+                    },
+                };
+                if matches { self.targets.push(path) }
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn device(metadata: &std::fs::Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device(_metadata: &std::fs::Metadata) -> Option<u64> { None }
+
+fn passes_filters(
+    options: &Options, 
+    root: &std::path::Path, 
+    path: &std::path::Path, 
+    metadata: &std::fs::Metadata,
+) -> bool {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let relative = relative.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    let matches = |pattern: &&str| {
+        match pattern.contains('/') {
+            true => glob_match(pattern, &relative),
+            false => glob_match(pattern, relative.rsplit('/').next().unwrap_or(&relative)),
+        }
+    };
+
+    if !options.include.is_empty() && !options.include.iter().any(matches) { return false }
+    if options.exclude.iter().any(matches) { return false }
+
+    if let Some(min_size) = options.min_size {
+        if metadata.len() < min_size { return false }
+    }
+
+    if let Some(older_than) = options.older_than {
+        let age = metadata.modified().ok().and_then(|m| m.elapsed().ok());
+        if !matches!(age, Some(age) if age > older_than) { return false }
+    }
+
+    true
+}
+
+/// Matches a path against a glob pattern.
+///
+/// `*` matches within a path component, `?` matches a single character within a path component,
+/// and `**` matches across components, so `**/Tournament/**` matches every file under a `Tournament` directory.
+/// `**/` at the start of a component also matches no components, so `**/*.slp` matches `Game.slp`.
+///
+/// In `Options`, paths are relative to the targeted directory and separated with `/`.
+/// Patterns without a `/` are matched against the file name only.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let (p, s) = (pattern.as_bytes(), path.as_bytes());
+    let (mut pi, mut si) = (0, 0);
+
+    // Where to retry from if the rest of the pattern fails: the pattern after the last `*` and
+    // the start of the path it matches, and the same for the last `**`, and whether it is a `**/`.
+    // A later star can match anything an earlier one can, so only the last of each is kept.
+    let mut star: Option<(usize, usize)> = None;
+    let mut globstar: Option<(usize, usize, bool)> = None;
+
+    loop {
+        if p[pi..].starts_with(b"**") {
+            let components = p[pi..].starts_with(b"**/") && (pi == 0 || p[pi - 1] == b'/');
+            pi += if components { 3 } else { 2 };
+            globstar = Some((pi, si, components));
+            star = None;
+            continue;
+        }
+
+        match p.get(pi) {
+            Some(b'*') => {
+                pi += 1;
+                star = Some((pi, si));
+                continue;
+            }
+            Some(b'?') if s.get(si).is_some_and(|&c| c != b'/') => {
+                pi += 1;
+                si += 1;
+                continue;
+            }
+            Some(&c) if c != b'?' && s.get(si) == Some(&c) => {
+                pi += 1;
+                si += 1;
+                continue;
+            }
+            None if si == s.len() => return true,
+            _ => (),
+        }
+
+        // let the last `*` match one more character
+        if let Some((star_pi, star_si)) = star {
+            if s.get(star_si).is_some_and(|&c| c != b'/') {
+                star = Some((star_pi, star_si + 1));
+                (pi, si) = (star_pi, star_si + 1);
+                continue;
+            }
+        }
+
+        // let the last `**` match one more character, or `**/` one more component
+        if let Some((glob_pi, glob_si, components)) = globstar {
+            let next = match components {
+                true => s[glob_si..].iter().position(|&c| c == b'/').map(|i| glob_si + i + 1),
+                false => (glob_si < s.len()).then_some(glob_si + 1),
+            };
+            if let Some(next) = next {
+                globstar = Some((glob_pi, next, components));
+                star = None;
+                (pi, si) = (glob_pi, next);
+                continue;
+            }
+        }
+
+        return false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn overwrite_read_only_output() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("slpz-read-only-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let slp_path = dir.join("game.slp");
+        let slp = crate::tests::replay(-123, 50);
+        std::fs::write(&slp_path, &slp).unwrap();
+        std::fs::set_permissions(&slp_path, std::fs::Permissions::from_mode(0o444)).unwrap();
+
+        let options = Options { compress: Some(true), log: false, ..Options::DEFAULT };
+        let mut compressor = Compressor::new(3).unwrap();
+        let mut buffers = Buffers::default();
+        for _ in 0..2 {
+            let outcome = compress_file(&mut compressor, &options, &slp_path, &mut buffers).unwrap();
+            assert_eq!(outcome, Outcome::Compressed(dir.join("game.slpz")));
+        }
+
+        let slpz_path = dir.join("game.slpz");
+        let mode = std::fs::metadata(&slpz_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o444);
+        let slpz = std::fs::read(&slpz_path).unwrap();
+        assert_eq!(crate::decompress(&mut Decompressor::new().unwrap(), &slpz), Ok(slp));
+        assert!(!dir.join("game.slpz.tmp").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("*.slp", "Game_20240101T120000.slp"));
+        assert!(!glob_match("*.slp", "Game.slpz"));
+        assert!(!glob_match("*.slp", "2024/Game.slp"));
+        assert!(glob_match("Game_????????T*", "Game_20240101T120000.slp"));
+        assert!(!glob_match("?", "/"));
+        assert!(!glob_match("?", ""));
+
+        assert!(glob_match("**/Tournament/**", "2024/Tournament/Game.slp"));
+        assert!(glob_match("**/Tournament/**", "Tournament/Game.slp"));
+        assert!(glob_match("**/*.slp", "a/b/c/Game.slp"));
+        assert!(glob_match("**/*.slp", "Game.slp"));
+        assert!(!glob_match("**/Tournament/**", "2024/Tournaments/Game.slp"));
+        assert!(glob_match("2024/*/Game.slp", "2024/01/Game.slp"));
+        assert!(!glob_match("2024/*/Game.slp", "2024/01/02/Game.slp"));
+        assert!(glob_match("2024/**", "2024/01/02/Game.slp"));
+
+        assert!(glob_match("", ""));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("a", "ab"));
+        assert!(glob_match("a**b", "a/x/b"));
+        assert!(!glob_match("a**/b", "ab"));
+
+        // backtracking is not exponential
+        let path = "a".repeat(100);
+        assert!(!glob_match(&"*a".repeat(30), &format!("{}/", path)));
+        assert!(!glob_match(&"**a".repeat(30), &format!("{}b", path)));
+        assert!(glob_match(&["**/*a"; 30].join("/"), &["a"; 40].join("/")));
+        assert!(!glob_match(&["**/*a"; 30].join("/"), &["a"; 20].join("/")));
+    }
+}
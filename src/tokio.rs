@@ -0,0 +1,256 @@
+//! Async compression and decompression of files and directories for tokio.
+//!
+//! File access and zstd run on tokio's blocking thread pool,
+//! where each thread reuses its own compression contexts and buffers.
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+
+use crate::{Compressor, Decompressor, Options, Outcome, TargetError, TargetPathError};
+use crate::target::{self, Buffers};
+
+thread_local! {
+    static COMPRESSOR: RefCell<Option<(i32, Compressor)>> = const { RefCell::new(None) };
+    static DECOMPRESSOR: RefCell<Option<Decompressor>> = const { RefCell::new(None) };
+    static BUFFERS: RefCell<Option<Buffers>> = const { RefCell::new(None) };
+}
+
+/// Compresses a file, writing the slpz file next to it.
+///
+/// `options.keep`, `options.existing` and `options.level` are respected. Nothing is logged.
+pub async fn compress_file(options: &Options, path: impl Into<PathBuf>) -> Result<Outcome, TargetError> {
+    let options = *options;
+    let path = path.into();
+    run_blocking(move || {
+        COMPRESSOR.with_borrow_mut(|compressor| {
+            let c = match compressor {
+                Some((level, c)) if *level == options.level => c,
+                _ => {
+                    let c = Compressor::new(options.level).ok_or(TargetError::ZstdInitError)?;
+                    &mut compressor.insert((options.level, c)).1
+                }
+            };
+            BUFFERS.with_borrow_mut(|buffers| {
+                target::compress_file(c, &options, &path, buffers.get_or_insert_with(Buffers::default))
+            })
+        })
+    }).await
+}
+
+/// Decompresses a file, writing the slp file next to it.
+///
+/// `options.keep` and `options.existing` are respected. Nothing is logged.
+pub async fn decompress_file(options: &Options, path: impl Into<PathBuf>) -> Result<Outcome, TargetError> {
+    let options = *options;
+    let path = path.into();
+    run_blocking(move || {
+        DECOMPRESSOR.with_borrow_mut(|decompressor| {
+            let d = match decompressor {
+                Some(d) => d,
+                None => decompressor.insert(Decompressor::new().ok_or(TargetError::ZstdInitError)?),
+            };
+            BUFFERS.with_borrow_mut(|buffers| {
+                target::decompress_file(d, &options, &path, buffers.get_or_insert_with(Buffers::default))
+            })
+        })
+    }).await
+}
+
+async fn run_blocking(
+    f: impl FnOnce() -> Result<Outcome, TargetError> + Send + 'static,
+) -> Result<Outcome, TargetError> {
+    match ::tokio::task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(TargetError::Cancelled),
+    }
+}
+
+/// The outcome of a single file in a `Batch`, 
+/// or a directory that could not be read, which has a `TargetError::Read` result.
+#[derive(Debug)]
+pub struct FileResult {
+    pub target: PathBuf,
+    pub result: Result<Outcome, TargetError>,
+}
+
+/// Files being compressed or decompressed in the background. Created by `target_path`.
+///
+/// Yields a `FileResult` as each file finishes, and ends once every file is finished.
+/// Dropping the batch cancels it.
+pub struct Batch {
+    total: usize,
+    results: ::tokio::sync::mpsc::Receiver<FileResult>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Batch {
+    /// The number of files targeted, plus the number of directories that could not be read.
+    pub fn total(&self) -> usize { self.total }
+
+    /// Stops starting new files. Files that have already started will still be yielded.
+    pub fn cancel(&self) { self.cancelled.store(true, Ordering::Relaxed) }
+}
+
+impl futures_core::Stream for Batch {
+    type Item = FileResult;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<FileResult>> {
+        self.results.poll_recv(cx)
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) { self.cancel() }
+}
+
+/// Async version of `target_path`. Must be called from within a tokio runtime.
+///
+/// Files are chosen the same way as `target_path`.
+/// If `options.threading` is set, one file per core is processed at once.
+/// Nothing is logged.
+pub async fn target_path(options: &Options, path: impl Into<PathBuf>) -> Result<Batch, TargetPathError> {
+    let options = *options;
+    let path = path.into();
+    let targets = match ::tokio::task::spawn_blocking(move || target::find_targets(&options, &path)).await {
+        Ok(res) => res?,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => return Err(TargetPathError::PathInvalid),
+    };
+
+    let concurrency = match options.threading {
+        true => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        false => 1,
+    };
+
+    let total = targets.files.len() + targets.unreadable.len();
+    let (sender, results) = ::tokio::sync::mpsc::channel(concurrency);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancelled_ref = cancelled.clone();
+
+    ::tokio::spawn(async move {
+        let will_compress = targets.will_compress;
+        for (target, e) in targets.unreadable {
+            let file = FileResult { target, result: Err(TargetError::Read(e)) };
+            if sender.send(file).await.is_err() { return }
+        }
+
+        let mut targets = targets.files.into_iter();
+        let mut running = ::tokio::task::JoinSet::new();
+
+        loop {
+            while running.len() < concurrency && !cancelled_ref.load(Ordering::Relaxed) {
+                let Some(target) = targets.next() else { break };
+                running.spawn(async move {
+                    let result = match will_compress {
+                        true => compress_file(&options, target.clone()).await,
+                        false => decompress_file(&options, target.clone()).await,
+                    };
+                    FileResult { target, result }
+                });
+            }
+
+            match running.join_next().await {
+                Some(Ok(file)) => {
+                    // the batch was dropped
+                    if sender.send(file).await.is_err() { cancelled_ref.store(true, Ordering::Relaxed) }
+                }
+                Some(Err(e)) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                Some(Err(_)) => (),
+                None => break,
+            }
+        }
+    });
+
+    Ok(Batch { total, results, cancelled })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str, replays: usize) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("slpz-tokio-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for i in 0..replays {
+            std::fs::write(dir.join(format!("{}.slp", i)), crate::tests::replay(-123, i as i32 * 10)).unwrap();
+        }
+        dir
+    }
+
+    async fn next(batch: &mut Batch) -> Option<FileResult> {
+        std::future::poll_fn(|cx| futures_core::Stream::poll_next(Pin::new(&mut *batch), cx)).await
+    }
+
+    fn count(dir: &std::path::Path, extension: &str) -> usize {
+        std::fs::read_dir(dir).unwrap()
+            .filter(|f| f.as_ref().unwrap().path().extension().is_some_and(|e| e == extension))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let dir = test_dir("round-trip", 1);
+        let slp_path = dir.join("0.slp");
+        let slp = std::fs::read(&slp_path).unwrap();
+        let options = Options { keep: false, ..Options::DEFAULT };
+
+        let outcome = compress_file(&options, &slp_path).await.unwrap();
+        assert_eq!(outcome, Outcome::Compressed(dir.join("0.slpz")));
+        assert!(!slp_path.exists());
+
+        let outcome = decompress_file(&options, dir.join("0.slpz")).await.unwrap();
+        assert_eq!(outcome, Outcome::Decompressed(slp_path.clone()));
+        assert_eq!(std::fs::read(&slp_path).unwrap(), slp);
+        assert!(matches!(decompress_file(&options, dir.join("0.slpz")).await, Err(TargetError::Read(_))));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn batch() {
+        let dir = test_dir("batch", 3);
+        let options = Options { compress: Some(true), ..Options::DEFAULT };
+
+        let mut batch = target_path(&options, &dir).await.unwrap();
+        assert_eq!(batch.total(), 3);
+        let mut targets = Vec::new();
+        while let Some(file) = next(&mut batch).await {
+            let mut out = file.target.clone();
+            out.set_extension("slpz");
+            assert_eq!(file.result.unwrap(), Outcome::Compressed(out));
+            targets.push(file.target);
+        }
+        targets.sort();
+        assert_eq!(targets, (0..3).map(|i| dir.join(format!("{}.slp", i))).collect::<Vec<_>>());
+        assert_eq!(count(&dir, "slpz"), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_batch() {
+        let dir = test_dir("dropped-batch", 40);
+        let options = Options { compress: Some(true), threading: false, ..Options::DEFAULT };
+
+        let mut batch = target_path(&options, &dir).await.unwrap();
+        assert!(next(&mut batch).await.unwrap().result.is_ok());
+        drop(batch);
+
+        // let files that already started finish
+        for _ in 0..10 {
+            ::tokio::task::spawn_blocking(|| std::thread::sleep(std::time::Duration::from_millis(20))).await.unwrap();
+        }
+
+        // one file is read, one waits in the channel and one waits to be sent when the batch is dropped
+        let compressed = count(&dir, "slpz");
+        assert!((1..=4).contains(&compressed), "{} files compressed", compressed);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+//! Cuts a range of frames out of a replay.
+
+use alloc::vec::Vec;
+
+use crate::{ubjson, SlpLayout, TrimError};
+use crate::{PRE_FRAME_UPDATE, POST_FRAME_UPDATE, GAME_END, FRAME_START, ITEM_UPDATE, FRAME_BOOKEND};
+
+/// Game End method for games that ended without a winner.
+const NO_CONTEST: u8 = 7;
+
+/// Cuts an slp file down to the frames from `from_frame` to `to_frame` inclusive.
+///
+/// Frames are numbered as in the replay, where the first frame is -123 and the game starts at 0.
+/// Dolphin replays a game by simulating it from its first frame, so frames before `from_frame` are kept
+/// and `from_frame` is written to the metadata as `startFrame` for players to start playback at.
+/// Frames after `to_frame` are removed, in which case Game End is replaced with a no contest.
+/// Metadata `lastFrame` is updated.
+///
+/// To trim an slpz file, decompress it first.
+pub fn trim(slp: &[u8], from_frame: i32, to_frame: i32) -> Result<Vec<u8>, TrimError> {
+    let SlpLayout { event_sizes, game_start_offset, game_start_size, metadata_offset, .. } 
+        = SlpLayout::read(slp).map_err(|_| TrimError::InvalidFile)?;
+    let events_offset = game_start_offset + game_start_size;
+    let mut metadata = ubjson::read_metadata(&slp[metadata_offset..]).ok_or(TrimError::InvalidMetadata)?;
+
+    let mut out = Vec::with_capacity(slp.len());
+    out.extend_from_slice(&slp[..events_offset]);
+
+    // events belong to the frame of the last frame event before them
+    let mut frame = None;
+    let mut last_frame = None;
+    let mut in_range = false;
+    let mut lead_in = false;
+    let mut truncated = false;
+    let mut i = events_offset;
+    while i < metadata_offset {
+        let command = slp[i];
+        let size = event_sizes[command as usize] as usize;
+        if size == 0 || i + 1 + size > metadata_offset { return Err(TrimError::InvalidFile) }
+        let event = &slp[i..][..1 + size];
+        i += 1 + size;
+
+        if let PRE_FRAME_UPDATE | POST_FRAME_UPDATE | FRAME_START | ITEM_UPDATE | FRAME_BOOKEND = command {
+            let bytes = event.get(1..5).ok_or(TrimError::InvalidFile)?;
+            frame = Some(i32::from_be_bytes(bytes.try_into().unwrap()));
+        }
+
+        match frame {
+            Some(f) if f > to_frame => {
+                truncated = true;
+                continue
+            }
+            Some(f) => {
+                in_range |= f >= from_frame;
+                lead_in |= f < from_frame;
+                last_frame = Some(f);
+            }
+            None => (),
+        }
+        out.extend_from_slice(event);
+    }
+    let last_frame = last_frame.filter(|_| in_range).ok_or(TrimError::EmptyRange)?;
+
+    // the original Game End was after the last frame, so was removed with it.
+    // the new one is a no contest with no LRAS initiator and unknown placements
+    let game_end_size = event_sizes[GAME_END as usize] as usize;
+    if truncated && game_end_size > 0 {
+        out.push(GAME_END);
+        let start = out.len();
+        out.resize(start + game_end_size, 0xFF);
+        out[start] = NO_CONTEST;
+    }
+
+    let raw_len = out.len() - 15;
+    let raw_len = u32::try_from(raw_len).map_err(|_| TrimError::InvalidFile)?;
+    out[11..15].copy_from_slice(&raw_len.to_be_bytes());
+
+    if metadata.get("lastFrame").is_some() {
+        metadata.insert("lastFrame", ubjson::Value::I32(last_frame));
+    }
+    if lead_in {
+        metadata.insert("startFrame", ubjson::Value::I32(from_frame));
+    }
+    ubjson::write_metadata(&metadata, &mut out);
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::replay;
+    use crate::{events, Decompressor, Format};
+    use alloc::vec;
+
+    /// Returns the frame of each Frame Start and the last event.
+    fn frames(slp: &[u8]) -> (Vec<i32>, (u8, Vec<u8>)) {
+        let mut decompressor = Decompressor::new().unwrap();
+        let mut events = events(&mut decompressor, slp).unwrap();
+        let mut frames = Vec::new();
+        let mut last = None;
+        while let Some((command, payload)) = events.next_event() {
+            if command == FRAME_START { frames.push(i32::from_be_bytes(payload[..4].try_into().unwrap())) }
+            last = Some((command, payload.to_vec()));
+        }
+        (frames, last.unwrap())
+    }
+
+    fn metadata_frame(slp: &[u8], key: &str) -> Option<i64> {
+        let metadata = ubjson::read_metadata(crate::sections(slp).unwrap().metadata).unwrap();
+        metadata.get(key)?.as_i64()
+    }
+
+    #[test]
+    fn frame_bounds() {
+        let slp = replay(-123, 200);
+
+        // frames before the clip are kept for playback
+        let trimmed = trim(&slp, 0, 99).unwrap();
+        assert_eq!(crate::detect_format(&trimmed), Format::Slp);
+        assert_eq!(frames(&trimmed), ((-123..=99).collect(), (GAME_END, vec![NO_CONTEST, 0xFF])));
+        assert_eq!(metadata_frame(&trimmed, "lastFrame"), Some(99));
+        assert_eq!(metadata_frame(&trimmed, "startFrame"), Some(0));
+
+        // the original Game End is kept if the end is not cut
+        let trimmed = trim(&slp, 150, i32::MAX).unwrap();
+        assert_eq!(frames(&trimmed), ((-123..=200).collect(), (GAME_END, vec![2, 0])));
+        assert_eq!(metadata_frame(&trimmed, "lastFrame"), Some(200));
+        assert_eq!(metadata_frame(&trimmed, "startFrame"), Some(150));
+
+        let trimmed = trim(&slp, i32::MIN, 200).unwrap();
+        assert_eq!(trimmed, slp);
+
+        assert_eq!(trim(&slp, 201, 300), Err(TrimError::EmptyRange));
+        assert_eq!(trim(&slp, 50, 10), Err(TrimError::EmptyRange));
+        assert_eq!(trim(&slp[..slp.len() / 2], 0, 10), Err(TrimError::InvalidFile));
+    }
+}
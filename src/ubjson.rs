@@ -0,0 +1,450 @@
+//! Minimal UBJSON, for reading and editing replay metadata.
+//!
+//! Values keep the exact types they were read with, so unedited values are written back unchanged.
+//! Optimized containers are read, but always written without the type and count markers.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Containers nested deeper than this are rejected.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    U8(u8),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Char(u8),
+    String(String),
+    /// A high precision number, as its string.
+    HighPrecision(String),
+    Array(Vec<Value>),
+    /// Keys are kept in file order.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Parses a single value. Returns the value and the number of bytes read.
+    pub fn parse(bytes: &[u8]) -> Option<(Value, usize)> {
+        let mut reader = Reader { bytes, i: 0 };
+        let marker = reader.byte()?;
+        let value = reader.value(marker, 0)?;
+        Some((value, reader.i))
+    }
+
+    pub fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Null => out.push(b'Z'),
+            Value::Bool(true) => out.push(b'T'),
+            Value::Bool(false) => out.push(b'F'),
+            Value::U8(n) => { out.push(b'U'); out.push(*n) }
+            Value::I8(n) => { out.push(b'i'); out.extend_from_slice(&n.to_be_bytes()) }
+            Value::I16(n) => { out.push(b'I'); out.extend_from_slice(&n.to_be_bytes()) }
+            Value::I32(n) => { out.push(b'l'); out.extend_from_slice(&n.to_be_bytes()) }
+            Value::I64(n) => { out.push(b'L'); out.extend_from_slice(&n.to_be_bytes()) }
+            Value::F32(n) => { out.push(b'd'); out.extend_from_slice(&n.to_be_bytes()) }
+            Value::F64(n) => { out.push(b'D'); out.extend_from_slice(&n.to_be_bytes()) }
+            Value::Char(c) => { out.push(b'C'); out.push(*c) }
+            Value::String(s) => { out.push(b'S'); write_str(s, out) }
+            Value::HighPrecision(s) => { out.push(b'H'); write_str(s, out) }
+            Value::Array(values) => {
+                out.push(b'[');
+                for v in values { v.write(out) }
+                out.push(b']');
+            }
+            Value::Object(entries) => {
+                out.push(b'{');
+                for (k, v) in entries {
+                    write_str(k, out);
+                    v.write(out);
+                }
+                out.push(b'}');
+            }
+        }
+    }
+
+    /// Returns the value of a key in an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        match self {
+            Value::Object(entries) => entries.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Sets the value of a key in an object, keeping its position if it exists.
+    /// Returns false if this is not an object.
+    pub fn insert(&mut self, key: &str, value: Value) -> bool {
+        let Value::Object(entries) = self else { return false };
+        match entries.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => entries.push((key.to_string(), value)),
+        }
+        true
+    }
+
+    /// Removes a key from an object.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let Value::Object(entries) = self else { return None };
+        let i = entries.iter().position(|(k, _)| k == key)?;
+        Some(entries.remove(i).1)
+    }
+
+    /// Follows a path of object keys separated with '.', such as `players.0.names.netplay`.
+    pub fn path(&self, path: &str) -> Option<&Value> {
+        path.split('.').try_fold(self, |v, key| v.get(key))
+    }
+
+    pub fn path_mut(&mut self, path: &str) -> Option<&mut Value> {
+        path.split('.').try_fold(self, |v, key| v.get_mut(key))
+    }
+
+    /// Sets the value at a path, creating missing objects along the way.
+    /// Returns false if the path goes through a value that is not an object.
+    pub fn insert_path(&mut self, path: &str, value: Value) -> bool {
+        let (parents, key) = match path.rsplit_once('.') {
+            Some((parents, key)) => (Some(parents), key),
+            None => (None, path),
+        };
+
+        let mut object = self;
+        for parent in parents.into_iter().flat_map(|p| p.split('.')) {
+            if object.get(parent).is_none() && !object.insert(parent, Value::Object(Vec::new())) {
+                return false
+            }
+            object = object.get_mut(parent).unwrap();
+        }
+        object.insert(key, value)
+    }
+
+    /// Removes the value at a path.
+    pub fn remove_path(&mut self, path: &str) -> Option<Value> {
+        match path.rsplit_once('.') {
+            Some((parents, key)) => self.path_mut(parents)?.remove(key),
+            None => self.remove(path),
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns integers of any size.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::U8(n) => Some(n as i64),
+            Value::I8(n) => Some(n as i64),
+            Value::I16(n) => Some(n as i64),
+            Value::I32(n) => Some(n as i64),
+            Value::I64(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Writes the value as JSON.
+    ///
+    /// Non-finite floats become null, and high precision numbers and chars become strings.
+    pub fn write_json(&self, out: &mut String) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Value::F32(n) if !n.is_finite() => out.push_str("null"),
+            Value::F64(n) if !n.is_finite() => out.push_str("null"),
+            Value::F32(n) => out.push_str(&n.to_string()),
+            Value::F64(n) => out.push_str(&n.to_string()),
+            Value::Char(c) => write_json_str(&(*c as char).to_string(), out),
+            Value::String(s) | Value::HighPrecision(s) => write_json_str(s, out),
+            Value::Array(values) => {
+                out.push('[');
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 { out.push(',') }
+                    v.write_json(out);
+                }
+                out.push(']');
+            }
+            Value::Object(entries) => {
+                out.push('{');
+                for (i, (k, v)) in entries.iter().enumerate() {
+                    if i > 0 { out.push(',') }
+                    write_json_str(k, out);
+                    out.push(':');
+                    v.write_json(out);
+                }
+                out.push('}');
+            }
+            _ => out.push_str(&self.as_i64().unwrap().to_string()),
+        }
+    }
+
+    /// The smallest integer type that holds `n`.
+    pub fn int(n: i64) -> Value {
+        if let Ok(n) = u8::try_from(n) { return Value::U8(n) }
+        if let Ok(n) = i8::try_from(n) { return Value::I8(n) }
+        if let Ok(n) = i16::try_from(n) { return Value::I16(n) }
+        if let Ok(n) = i32::try_from(n) { return Value::I32(n) }
+        Value::I64(n)
+    }
+}
+
+/// Reads the metadata section of an slp or slpz file, as returned by `sections`.
+///
+/// The section is the 'metadata' key and value followed by the closing brace of the slp file.
+/// Replays without metadata have an empty object.
+pub fn read_metadata(section: &[u8]) -> Option<Value> {
+    if section == b"}" { return Some(Value::Object(Vec::new())) }
+
+    let mut reader = Reader { bytes: section, i: 0 };
+    if reader.str()? != "metadata" { return None }
+    let marker = reader.byte()?;
+    let metadata = reader.value(marker, 0)?;
+    if !matches!(metadata, Value::Object(_)) { return None }
+
+    match &section[reader.i..] {
+        b"}" => Some(metadata),
+        _ => None,
+    }
+}
+
+/// Writes a metadata section for `replace_sections`.
+pub fn write_metadata(metadata: &Value, out: &mut Vec<u8>) {
+    write_str("metadata", out);
+    metadata.write(out);
+    out.push(b'}');
+}
+
+/// Writes a JSON string, with quotes.
+pub fn write_json_str(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&alloc::format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn write_str(s: &str, out: &mut Vec<u8>) {
+    Value::int(s.len() as i64).write(out);
+    out.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    i: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.i..self.i.checked_add(n)?)?;
+        self.i += n;
+        Some(bytes)
+    }
+
+    fn byte(&mut self) -> Option<u8> { Some(self.take(1)?[0]) }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> { self.take(N)?.try_into().ok() }
+
+    fn peek(&self) -> Option<u8> { self.bytes.get(self.i).copied() }
+
+    /// A non-negative integer, such as a length.
+    fn len(&mut self) -> Option<usize> {
+        let marker = self.byte()?;
+        let n = self.value(marker, MAX_DEPTH)?.as_i64()?;
+        usize::try_from(n).ok()
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        Some(core::str::from_utf8(bytes).ok()?.to_string())
+    }
+
+    fn value(&mut self, mut marker: u8, depth: usize) -> Option<Value> {
+        // no-ops, skipped here so long runs of them do not recurse
+        while marker == b'N' { marker = self.byte()? }
+
+        Some(match marker {
+            b'Z' => Value::Null,
+            b'T' => Value::Bool(true),
+            b'F' => Value::Bool(false),
+            b'U' => Value::U8(self.byte()?),
+            b'i' => Value::I8(i8::from_be_bytes(self.array()?)),
+            b'I' => Value::I16(i16::from_be_bytes(self.array()?)),
+            b'l' => Value::I32(i32::from_be_bytes(self.array()?)),
+            b'L' => Value::I64(i64::from_be_bytes(self.array()?)),
+            b'd' => Value::F32(f32::from_be_bytes(self.array()?)),
+            b'D' => Value::F64(f64::from_be_bytes(self.array()?)),
+            b'C' => Value::Char(self.byte()?),
+            b'S' => Value::String(self.str()?),
+            b'H' => Value::HighPrecision(self.str()?),
+            b'[' | b'{' => {
+                if depth >= MAX_DEPTH { return None }
+                let object = marker == b'{';
+                let end = if object { b'}' } else { b']' };
+
+                let mut ty = None;
+                if self.peek() == Some(b'$') {
+                    self.i += 1;
+                    ty = Some(self.byte()?);
+                }
+                let mut count = None;
+                if self.peek() == Some(b'#') {
+                    self.i += 1;
+                    count = Some(self.len()?);
+                }
+                if ty.is_some() && count.is_none() { return None }
+                if count.is_some_and(|count| count > self.bytes.len() - self.i) { return None }
+
+                let mut values = Vec::new();
+                let mut entries = Vec::new();
+                let mut n = 0;
+                loop {
+                    match count {
+                        Some(count) if n == count => break,
+                        None if self.peek()? == end => {
+                            self.i += 1;
+                            break;
+                        }
+                        _ => (),
+                    }
+
+                    let key = if object { Some(self.str()?) } else { None };
+                    let marker = match ty {
+                        Some(ty) => ty,
+                        None => self.byte()?,
+                    };
+                    let value = self.value(marker, depth + 1)?;
+                    match key {
+                        Some(key) => entries.push((key, value)),
+                        None => values.push(value),
+                    }
+                    n += 1;
+                }
+
+                match object {
+                    true => Value::Object(entries),
+                    false => Value::Array(values),
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn round_trip() {
+        let value = Value::Object(vec![
+            ("null".into(), Value::Null),
+            ("bools".into(), Value::Array(vec![Value::Bool(true), Value::Bool(false)])),
+            ("ints".into(), Value::Array(vec![
+                Value::U8(200), Value::I8(-5), Value::I16(-300), Value::I32(1 << 20), Value::I64(-1 << 40),
+            ])),
+            ("floats".into(), Value::Array(vec![Value::F32(1.5), Value::F64(-0.25)])),
+            ("char".into(), Value::Char(b'x')),
+            ("string".into(), Value::String("Genesis 10".into())),
+            ("high precision".into(), Value::HighPrecision("1e1000".into())),
+            ("nested".into(), Value::Object(vec![("empty".into(), Value::Object(vec![]))])),
+        ]);
+        let mut bytes = Vec::new();
+        value.write(&mut bytes);
+        assert_eq!(Value::parse(&bytes), Some((value, bytes.len())));
+    }
+
+    #[test]
+    fn optimized_containers() {
+        // typed and counted arrays, and a counted object, as written by other encoders
+        let bytes = b"[$U#U\x03\x01\x02\x03";
+        let ints = Value::Array(vec![Value::U8(1), Value::U8(2), Value::U8(3)]);
+        assert_eq!(Value::parse(bytes), Some((ints, bytes.len())));
+
+        let bytes = b"{#U\x01U\x01aZ";
+        let object = Value::Object(vec![("a".into(), Value::Null)]);
+        assert_eq!(Value::parse(bytes), Some((object, bytes.len())));
+    }
+
+    #[test]
+    fn malformed() {
+        let cases: [&[u8]; 9] = [
+            b"",
+            b"X",
+            b"l\x00\x01",
+            b"SU\x05abc",
+            b"S\x41",
+            b"SU\x02\xFF\xFF",
+            b"[U\x01",
+            b"{U\x01aU",
+            b"[$U]",
+        ];
+        for bytes in cases {
+            assert_eq!(Value::parse(bytes), None, "{:?}", bytes);
+        }
+
+        // counts larger than the remaining bytes
+        assert_eq!(Value::parse(b"[$Z#l\x7F\xFF\xFF\xFF"), None);
+        assert_eq!(Value::parse(b"[#L\xFF\xFF\xFF\xFF\xFF\xFF\xFF\xFF"), None);
+    }
+
+    #[test]
+    fn metadata() {
+        let mut metadata = Value::Object(Vec::new());
+        assert!(metadata.insert_path("players.0.names.netplay", Value::String("Player".into())));
+        assert!(!metadata.insert_path("players.0.names.netplay.x", Value::Null));
+
+        let mut section = Vec::new();
+        write_metadata(&metadata, &mut section);
+        assert_eq!(read_metadata(&section), Some(metadata));
+        assert_eq!(read_metadata(b"}"), Some(Value::Object(Vec::new())));
+
+        section.push(b'}');
+        assert_eq!(read_metadata(&section), None);
+        assert_eq!(read_metadata(b"U\x08metadataZ}"), None);
+    }
+
+    #[test]
+    fn no_ops() {
+        let mut bytes = alloc::vec![b'N'; 1 << 21];
+        bytes.extend_from_slice(b"U\x05");
+        assert_eq!(Value::parse(&bytes), Some((Value::U8(5), bytes.len())));
+
+        let bytes = alloc::vec![b'N'; 1 << 21];
+        assert_eq!(Value::parse(&bytes), None);
+    }
+
+    #[test]
+    fn max_depth() {
+        let nested = |depth: usize| {
+            let mut bytes = alloc::vec![b'['; depth];
+            bytes.extend(core::iter::repeat_n(b']', depth));
+            bytes
+        };
+        assert!(Value::parse(&nested(MAX_DEPTH)).is_some());
+        assert_eq!(Value::parse(&nested(MAX_DEPTH + 1)), None);
+        assert_eq!(Value::parse(&nested(1 << 20)), None);
+    }
+}
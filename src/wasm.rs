@@ -0,0 +1,114 @@
+//! WebAssembly bindings for use in the browser or node.
+//!
+//! Build with wasm-bindgen:
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown --no-default-features --features wasm --crate-type cdylib
+//! wasm-bindgen --target web --out-dir target/wasm-pkg target/wasm32-unknown-unknown/release/slpz.wasm
+//! ```
+//! Byte arguments and return values are `Uint8Array`s.
+
+use wasm_bindgen::prelude::*;
+
+fn js_error(e: impl std::fmt::Display) -> JsError { JsError::new(&e.to_string()) }
+
+/// Reusable compression context.
+#[wasm_bindgen(js_name = Compressor)]
+pub struct WasmCompressor { compressor: crate::Compressor }
+
+/// Reusable decompression context.
+#[wasm_bindgen(js_name = Decompressor)]
+pub struct WasmDecompressor { decompressor: crate::Decompressor }
+
+#[wasm_bindgen(js_class = Compressor)]
+impl WasmCompressor {
+    /// level should be between 1 and 19. The default is 3.
+    #[wasm_bindgen(constructor)]
+    pub fn new(level: Option<i32>) -> Result<WasmCompressor, JsError> {
+        let compressor = crate::Compressor::new(level.unwrap_or(3))
+            .ok_or_else(|| JsError::new("Failed to init zstd"))?;
+        Ok(WasmCompressor { compressor })
+    }
+
+    /// Compresses an slp file to an slpz file.
+    pub fn compress(&mut self, slp: &[u8]) -> Result<Vec<u8>, JsError> {
+        crate::compress(&mut self.compressor, slp).map_err(js_error)
+    }
+}
+
+#[wasm_bindgen(js_class = Decompressor)]
+impl WasmDecompressor {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Result<WasmDecompressor, JsError> {
+        let decompressor = crate::Decompressor::new()
+            .ok_or_else(|| JsError::new("Failed to init zstd"))?;
+        Ok(WasmDecompressor { decompressor })
+    }
+
+    /// Decompresses an slpz file to an slp file.
+    pub fn decompress(&mut self, slpz: &[u8]) -> Result<Vec<u8>, JsError> {
+        crate::decompress(&mut self.decompressor, slpz).map_err(js_error)
+    }
+}
+
+/// The header at the start of every slpz file. Offsets are from file start.
+#[wasm_bindgen(js_name = Header)]
+#[derive(Copy, Clone)]
+pub struct WasmHeader {
+    pub version: u32,
+    pub event_sizes_offset: usize,
+    pub game_start_offset: usize,
+    pub metadata_offset: usize,
+    pub compressed_events_offset: usize,
+    pub decompressed_events_size: usize,
+    /// The exact size of the slp file this slpz file decompresses to.
+    pub decompressed_size: usize,
+}
+
+/// Compresses an slp file to an slpz file.
+#[wasm_bindgen]
+pub fn compress(slp: &[u8], level: Option<i32>) -> Result<Vec<u8>, JsError> {
+    WasmCompressor::new(level)?.compress(slp)
+}
+
+/// Decompresses an slpz file to an slp file.
+#[wasm_bindgen]
+pub fn decompress(slpz: &[u8]) -> Result<Vec<u8>, JsError> {
+    WasmDecompressor::new()?.decompress(slpz)
+}
+
+/// Reads and validates the header of an slpz file.
+#[wasm_bindgen(js_name = readHeader)]
+pub fn read_header(slpz: &[u8]) -> Result<WasmHeader, JsError> {
+    let header = crate::Header::read(slpz).map_err(js_error)?;
+    Ok(WasmHeader {
+        version: header.version,
+        event_sizes_offset: header.event_sizes_offset,
+        game_start_offset: header.game_start_offset,
+        metadata_offset: header.metadata_offset,
+        compressed_events_offset: header.compressed_events_offset,
+        decompressed_events_size: header.decompressed_events_size,
+        decompressed_size: header.decompressed_size(),
+    })
+}
+
+/// Returns the exact size of the slp file an slpz file decompresses to, without decompressing it.
+#[wasm_bindgen(js_name = decompressedSize)]
+pub fn decompressed_size(slpz: &[u8]) -> Result<usize, JsError> {
+    crate::decompressed_size(slpz).map_err(js_error)
+}
+
+/// Returns the uncompressed Game Start event of an slpz file, including the command byte.
+#[wasm_bindgen(js_name = gameStart)]
+pub fn game_start(slpz: &[u8]) -> Result<Vec<u8>, JsError> {
+    let header = crate::Header::read(slpz).map_err(js_error)?;
+    Ok(slpz[header.game_start_offset..header.metadata_offset].to_vec())
+}
+
+/// Returns the uncompressed metadata of an slpz file.
+///
+/// This is the UBJSON 'metadata' key and value followed by the closing brace of the slp file.
+#[wasm_bindgen]
+pub fn metadata(slpz: &[u8]) -> Result<Vec<u8>, JsError> {
+    let header = crate::Header::read(slpz).map_err(js_error)?;
+    Ok(slpz[header.metadata_offset..header.compressed_events_offset].to_vec())
+}
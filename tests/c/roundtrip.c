@@ -0,0 +1,120 @@
+/* Round-trips slp files through the slpz C ABI.
+ *
+ * Build and run from the repo root:
+ *   cargo rustc --lib --release --features ffi --crate-type staticlib
+ *   cc -Iinclude tests/c/roundtrip.c target/release/libslpz.a -lpthread -ldl -lm -o target/roundtrip
+ *   target/roundtrip replay.slp...
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "slpz.h"
+
+static uint8_t *read_file(const char *path, size_t *len) {
+    FILE *f = fopen(path, "rb");
+    if (f == NULL) return NULL;
+    fseek(f, 0, SEEK_END);
+    long size = ftell(f);
+    fseek(f, 0, SEEK_SET);
+
+    uint8_t *buf = malloc(size);
+    if (buf != NULL && fread(buf, 1, size, f) != (size_t)size) {
+        free(buf);
+        buf = NULL;
+    }
+    fclose(f);
+    *len = size;
+    return buf;
+}
+
+static int roundtrip(SlpzCompressor *c, SlpzDecompressor *d, const char *path) {
+    size_t slp_len;
+    uint8_t *slp = read_file(path, &slp_len);
+    if (slp == NULL) {
+        fprintf(stderr, "%s: could not read file\n", path);
+        return 1;
+    }
+
+    int failed = 1;
+    uint8_t *slpz = NULL;
+    uint8_t *out = NULL;
+
+    size_t slpz_cap = slpz_compress_bound(slp_len);
+    size_t slpz_len;
+    slpz = malloc(slpz_cap);
+    int32_t err = slpz_compress(c, slp, slp_len, slpz, slpz_cap, &slpz_len);
+    if (err != SLPZ_OK) {
+        fprintf(stderr, "%s: compression failed with error %d\n", path, err);
+        goto done;
+    }
+
+    SlpzHeader header;
+    err = slpz_read_header(slpz, slpz_len, &header);
+    if (err != SLPZ_OK) {
+        fprintf(stderr, "%s: reading header failed with error %d\n", path, err);
+        goto done;
+    }
+    if (header.decompressed_size != slp_len) {
+        fprintf(stderr, "%s: header reports size %zu, expected %zu\n", path, header.decompressed_size, slp_len);
+        goto done;
+    }
+
+    /* a buffer that is too small must be rejected and report the required size */
+    size_t out_len = 0;
+    uint8_t small[16];
+    err = slpz_decompress(d, slpz, slpz_len, small, sizeof(small), &out_len);
+    if (err != SLPZ_ERR_BUFFER_TOO_SMALL || out_len != slp_len) {
+        fprintf(stderr, "%s: expected SLPZ_ERR_BUFFER_TOO_SMALL, got error %d\n", path, err);
+        goto done;
+    }
+
+    out = malloc(out_len);
+    err = slpz_decompress(d, slpz, slpz_len, out, out_len, &out_len);
+    if (err != SLPZ_OK) {
+        fprintf(stderr, "%s: decompression failed with error %d\n", path, err);
+        goto done;
+    }
+    if (out_len != slp_len || memcmp(out, slp, slp_len) != 0) {
+        fprintf(stderr, "%s: round trip does not match original\n", path);
+        goto done;
+    }
+
+    /* slp files are not slpz files */
+    err = slpz_decompress(d, slp, slp_len, out, out_len, &out_len);
+    if (err != SLPZ_ERR_INVALID_FILE) {
+        fprintf(stderr, "%s: decompressing an slp file returned error %d\n", path, err);
+        goto done;
+    }
+
+    printf("%s: ok (%zu -> %zu bytes)\n", path, slp_len, slpz_len);
+    failed = 0;
+
+done:
+    free(out);
+    free(slpz);
+    free(slp);
+    return failed;
+}
+
+int main(int argc, char **argv) {
+    if (argc < 2) {
+        fprintf(stderr, "Usage: %s <slp files>\n", argv[0]);
+        return 1;
+    }
+
+    SlpzCompressor *c = slpz_compressor_new(3);
+    SlpzDecompressor *d = slpz_decompressor_new();
+    if (c == NULL || d == NULL) {
+        fprintf(stderr, "failed to create contexts\n");
+        return 1;
+    }
+
+    int failures = 0;
+    for (int i = 1; i < argc; ++i) failures += roundtrip(c, d, argv[i]);
+
+    slpz_compressor_free(c);
+    slpz_decompressor_free(d);
+    return failures != 0;
+}
@@ -0,0 +1,46 @@
+// Round-trips slp files through the slpz WebAssembly bindings.
+//
+// Build and run from the repo root:
+//   cargo rustc --lib --release --target wasm32-unknown-unknown --no-default-features --features wasm --crate-type cdylib
+//   wasm-bindgen --target nodejs --out-dir target/wasm-pkg target/wasm32-unknown-unknown/release/slpz.wasm
+//   node tests/wasm/roundtrip.mjs replay.slp...
+
+import { readFileSync } from "node:fs";
+import { createRequire } from "node:module";
+
+const require = createRequire(import.meta.url);
+const slpz = require("../../target/wasm-pkg/slpz.js");
+
+const paths = process.argv.slice(2);
+if (paths.length === 0) {
+    console.error("Usage: node tests/wasm/roundtrip.mjs <slp files>");
+    process.exit(1);
+}
+
+const compressor = new slpz.Compressor(3);
+const decompressor = new slpz.Decompressor();
+let failures = 0;
+
+for (const path of paths) {
+    const slp = new Uint8Array(readFileSync(path));
+    try {
+        const compressed = compressor.compress(slp);
+        const header = slpz.readHeader(compressed);
+        if (header.decompressed_size !== slp.length) throw new Error(`header reports size ${header.decompressed_size}`);
+        if (slpz.gameStart(compressed)[0] !== 0x36) throw new Error("game start is missing");
+
+        const roundTrip = decompressor.decompress(compressed);
+        if (Buffer.compare(roundTrip, slp) !== 0) throw new Error("round trip does not match original");
+
+        let rejected = false;
+        try { slpz.decompress(slp); } catch { rejected = true; }
+        if (!rejected) throw new Error("decompressing an slp file did not fail");
+
+        console.log(`${path}: ok (${slp.length} -> ${compressed.length} bytes)`);
+    } catch (e) {
+        console.error(`${path}: ${e.message}`);
+        failures += 1;
+    }
+}
+
+process.exit(failures === 0 ? 0 : 1);